@@ -1,240 +1,1056 @@
-use std::{fs::File, io::BufReader};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result};
-use log::{error, info, warn};
-use rss::Channel;
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use reqwest::{
+    header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+
+pub mod report;
+pub mod scheduler;
+pub mod watch;
+
+use report::RunReport;
+use scheduler::Scheduler;
+use watch::ConfigWatcher;
 
 use crate::{
     client::{Client, UA},
     data::episode::Episode,
-    data::{config::Config, history::History, SyncFile},
+    data::filter::Filter,
+    data::template::{self, TemplateContext},
+    data::{
+        cache::{FeedCache, Validators},
+        config::{Config, FeedSource, OnRemoved, SerdeFeed},
+        history::{HistoryRecord, Outcome},
+        store::{HistoryStore, StateStore},
+        SyncFile,
+    },
     error::Error,
-    jsonrpc::JsonRPCBuilder,
+    feed::Feed,
+    jsonrpc::{
+        types::{Aria2Event, Aria2Notification, Aria2Status, Aria2Version},
+        JsonRPC, JsonRPCBuilder,
+    },
 };
 
 pub struct App<'a> {
     pub config: &'a mut Config<'a>,
-    pub history: &'a mut History<'a>,
+    pub history: &'a mut dyn HistoryStore,
     pub client: Client,
-    download_list: Vec<Episode>,
+    /// Persisted download list, contains episodes that we've picked up but not yet finished
+    state: &'a mut dyn StateStore,
+    /// Validators of web feeds for conditional fetching
+    cache: &'a mut FeedCache<'a>,
+    /// Validators of feeds fetched in this run, only cached once their episodes are saved,
+    /// otherwise the next run would get 304 and miss them
+    validators: Vec<(String, Validators)>,
+    /// Whether the download list loaded from state has been checked against aria2
+    reconciled: bool,
+    scheduler: Scheduler,
+    /// Set in watch mode to apply config edits right away
+    watcher: Option<ConfigWatcher>,
     ua: UA,
 }
 
+/// How often waiting is interrupted to check for config edits
+const CONFIG_POLL: Duration = Duration::from_secs(1);
+
 impl<'a> App<'a> {
-    pub fn new(config: &'a mut Config<'a>, history: &'a mut History<'a>) -> Result<Self> {
-        Self::with_ua(config, history)
+    pub fn new(
+        config: &'a mut Config<'a>,
+        history: &'a mut dyn HistoryStore,
+        state: &'a mut dyn StateStore,
+        cache: &'a mut FeedCache<'a>,
+    ) -> Result<Self> {
+        Self::with_ua(config, history, state, cache)
     }
 
-    pub fn with_ua(config: &'a mut Config<'a>, history: &'a mut History<'a>) -> Result<Self> {
+    pub fn with_ua(
+        config: &'a mut Config<'a>,
+        history: &'a mut dyn HistoryStore,
+        state: &'a mut dyn StateStore,
+        cache: &'a mut FeedCache<'a>,
+    ) -> Result<Self> {
         info!("Creating in-app client...");
-        let client = Client::new().map_err(|e| {
+        let mut client = Client::new().inspect_err(|_e| {
             warn!("Fail to create in-app client");
-            e
         })?;
+        client.set_retry_policy(config.retry_policy());
+        history.set_retention(config.retention());
 
         let ret = Self {
             config,
             history,
             client,
-            download_list: vec![],
+            state,
+            cache,
+            validators: vec![],
+            reconciled: false,
+            scheduler: Scheduler::new(),
+            watcher: None,
             ua: UA::default(),
         };
 
         Ok(ret)
     }
 
-    pub fn run(&mut self, dry_run: bool) -> Result<()> {
-        if self.check_aria2_connection() && !dry_run {
-            info!("Can't connect to aria2.");
-            info!("waiting for next loop.");
-            return Err(Error::Aria2ConnectionError.into());
+    /// Fetch due feeds and send new episodes to aria2.
+    ///
+    /// A broken feed or item is skipped and recorded in the returned report, only failures
+    /// affecting the whole run, like aria2 being unreachable, are returned as errors.
+    pub fn run(&mut self, dry_run: bool) -> Result<RunReport> {
+        let mut report = RunReport::default();
+        // left from a run that failed halfway
+        self.validators.clear();
+
+        if !dry_run {
+            self.check_aria2_connection().inspect_err(|e| {
+                info!("{e}.");
+                info!("waiting for next loop.");
+            })?;
         }
 
-        // reload
+        // reload, a dry run leaves every file as it is
         info!("P1 syncing config");
-        self.config
-            .sync()
-            .with_context(|| "Can't sync config (p1)")
-            .map_err(|e| {
-                warn!("P1 config sync failed: {}", e);
-                e
-            })?;
-        info!("P1 syncing history");
-        self.history
-            .sync()
-            .with_context(|| "Can't sync history (p1)")
-            .map_err(|e| {
-                warn!("P1 history sync failed: {}", e);
-                e
-            })?;
+        report.conflicts.extend(self.sync_config(dry_run));
+        if !dry_run {
+            info!("P1 syncing history");
+            self.history
+                .sync()
+                .with_context(|| "Can't sync history (p1)")
+                .map_err(|e| {
+                    warn!("P1 history sync failed: {}", e);
+                    e
+                })?;
+            info!("P1 syncing cache");
+            self.cache
+                .sync()
+                .with_context(|| "Can't sync cache (p1)")
+                .inspect_err(|e| warn!("P1 cache sync failed: {e}"))?;
+            info!("P1 syncing state");
+            self.state
+                .sync()
+                .with_context(|| "Can't sync state (p1)")
+                .inspect_err(|e| warn!("P1 state sync failed: {e}"))?;
+        }
 
-        // get episodes from rss
-        info!("Getting episodes from rss...");
-        info!("Getting rss channels...");
-        let channels = self.get_rss_channels().map_err(|e| {
-            warn!("Fail to getting rss channels: {e}");
-            e
-        })?;
+        if !dry_run && !self.reconciled {
+            self.reconcile(&mut report)?;
+            self.requeue_errors(&mut report);
+            self.reconciled = true;
+        }
+
+        // get episodes from feeds
+        info!("Getting episodes from feeds...");
+        info!("Getting feeds...");
+        let channels = self.get_feeds(&mut report);
+        let fetched: HashSet<String> = channels.iter().map(|(feed, _)| feed.name.clone()).collect();
         let mut episodes: Vec<Episode> = vec![];
         info!("Collecting episodes...");
-        for channel in channels {
+        for (feed, channel) in channels {
+            let filter = match feed.filter.as_ref().map(Filter::new).transpose() {
+                Ok(filter) => filter,
+                Err(e) => {
+                    warn!("Bad filter for feed {}: {e}", feed.name);
+                    report.fail(format!("feed {}", feed.name), format!("bad filter: {e}"));
+                    continue;
+                }
+            };
+            let extractor = self.config.link_extractor(Some(&feed.name));
             for item in channel.items {
-                episodes.push(Episode::try_from(item).map_err(|e| {
-                    warn!("Can't convert Item into Episode: {e}");
-                    e
-                })?)
+                let name = item.title.clone().or(item.guid.clone()).unwrap_or_default();
+                let mut epi = match Episode::from_item(item, &extractor) {
+                    Ok(epi) => epi,
+                    Err(e) => {
+                        warn!("Can't convert item {name} of feed {}: {e}", feed.name);
+                        report.skip(&feed.name, &name, e);
+                        continue;
+                    }
+                };
+                epi.feed = Some(feed.name.to_string());
+                if let Some(dir) = self.config.aria2_options(Some(&feed.name)).get("dir") {
+                    let context = TemplateContext {
+                        feed: &feed.name,
+                        channel: &channel.title,
+                        episode: &epi,
+                    };
+                    match template::expand(dir, &context) {
+                        Ok(dir) => epi.dir = Some(dir),
+                        Err(e) => {
+                            warn!("Bad dir for feed {}: {e}", feed.name);
+                            report.skip(&feed.name, &name, e);
+                            continue;
+                        }
+                    }
+                }
+                if filter.as_ref().is_none_or(|f| f.is_match(&epi)) {
+                    episodes.push(epi)
+                }
+            }
+        }
+        if !dry_run {
+            let now = Utc::now();
+            for epi in &episodes {
+                self.history.seen(&epi.guid, now);
+            }
+            // not modified, not due or failing, absence is only counted from what was fetched
+            for feed in self.config.enabled_feeds() {
+                if !fetched.contains(&feed.name) {
+                    self.history.seen_feed(&feed.name, now);
+                }
             }
         }
         let episodes = episodes
             .into_iter()
-            .filter(|epi| self.history.query(&epi.guid))
+            .filter(|epi| !self.history.query(&epi.guid))
             // merge episodes into download_list
             // download_list contains episode that we've sent to aria2
-            .filter(|epi| self.download_list.contains(epi));
+            .filter(|epi| !self.state.download_list().contains(epi));
+        let mut episodes = episodes.collect::<Vec<Episode>>();
 
-        self.download_list
-            .append(&mut episodes.collect::<Vec<Episode>>());
+        if dry_run {
+            // only show what would be sent, the next real run decides again
+            self.preview(&episodes, &mut report)?;
+            info!("Syncing download status");
+            self.sync_download_status(dry_run, &mut report)?;
+            info!("P2 syncing config...");
+            report.conflicts.extend(self.sync_config(dry_run));
+            return Ok(report);
+        }
+
+        self.state.download_list_mut().append(&mut episodes);
 
         // send episode to aria2
-        info!("Sending episodes to aria2");
-        for epi in self
-            .download_list
-            .iter_mut()
-            // only takes out what we need to send
-            .filter(|epi| epi.is_waiting())
-        {
-            let jsonrpc = JsonRPCBuilder::new(self.ua.as_str())
-                .aria2_add_uri(None, &epi.torrent_link)
-                .build()
-                .map_err(|e| {
-                    warn!("Fail to build JsonRPC: {e}");
-                    e
-                })?;
-            if !dry_run {
-                let response = self
-                    .client
-                    .send(self.config.aria2_address(), jsonrpc)
-                    .map_err(|e| {
-                        warn!("Fail to get JsonRPC's response: {e}");
-                        e
-                    })?;
-                // TODO: will this panic?
-                let gid = response.unwrap_response()?.get("gid").unwrap().to_string();
-                epi.gid = Some(gid);
-                epi.set_sent();
-            } else {
-                let response = self.client.dry_send(self.config.aria2_address(), jsonrpc)?;
-                println!("dry run: {}", response);
-            }
+        self.send_waiting(&mut report)?;
+        // keep gids on disk as soon as possible
+        self.state.sync().inspect_err(|e| {
+            warn!("Fail to save state after sending episodes: {e}");
+        })?;
+        for (url, validators) in self.validators.drain(..) {
+            self.cache.insert(&url, validators);
         }
 
         // sync download status
         info!("Syncing download status");
-        for epi in self.download_list.iter_mut().filter(|epi| epi.is_sent()) {
-            let jsonrpc = JsonRPCBuilder::new(self.ua.as_str())
-                .aria2_tell_status(None, &epi.gid()?)
-                .build()
-                .map_err(|e| {
-                    warn!("Fail to build JsonRPC: {e}");
-                    e
-                })?;
-            if !dry_run {
-                let response = self
-                    .client
-                    .send(self.config.aria2_address(), jsonrpc)
-                    .map_err(|e| {
-                        warn!("Fail to get JsonRPC's response: {e}");
-                        e
-                    })?;
-                let status = response
-                    .unwrap_response()?
-                    .get("status")
-                    .unwrap()
-                    .to_string();
-                epi.set_download_status(&status)?;
-            } else {
-                let response = self.client.dry_send(self.config.aria2_address(), jsonrpc)?;
-                println!("dry run: {}", response)
-            }
+        self.sync_download_status(dry_run, &mut report)?;
+        if self.requeue_errors(&mut report) {
+            self.send_waiting(&mut report)?;
         }
 
         // update history
         info!("Updating history...");
-        for epi in self.download_list.iter().filter(|epi| epi.is_done()) {
-            self.history.push(&epi.guid);
-        }
-
-        // remove items in download_list
-        self.download_list.retain(|epi| !epi.is_done());
+        self.update_history(&mut report);
 
         // write back
         info!("P2 syncing config...");
-        self.config.sync().map_err(|e| {
-            warn!("P2 config sync failed: {e}");
-            e
-        })?;
+        report.conflicts.extend(self.sync_config(dry_run));
         info!("P2 syncing history...");
         self.history.sync().map_err(|e| {
             warn!("P2 history sync failed: {e}");
             e
         })?;
+        info!("P2 syncing cache...");
+        self.cache
+            .sync()
+            .inspect_err(|e| warn!("P2 cache sync failed: {e}"))?;
+        info!("P2 syncing state...");
+        self.state
+            .sync()
+            .inspect_err(|e| warn!("P2 state sync failed: {e}"))?;
 
+        Ok(report)
+    }
+
+    /// Watch the config file, so edits are applied while waiting instead of on the next run.
+    pub fn watch_config(&mut self) -> Result<()> {
+        self.watcher = Some(ConfigWatcher::new(self.config.path())?);
         Ok(())
     }
 
-    fn get_rss_channels(&mut self) -> Result<Vec<Channel>> {
-        let mut ret: Vec<Channel> = vec![];
+    /// Sync config and apply it, returns conflicting keys.
+    ///
+    /// A config that fails to sync, e.g. an invalid edit, is logged and the last good one kept.
+    /// A dry run only reads the config, it is never written back.
+    fn sync_config(&mut self, dry_run: bool) -> Vec<String> {
+        let synced = if dry_run {
+            self.config.reload()
+        } else {
+            self.config.sync()
+        };
+        if let Err(e) = synced {
+            error!("Can't sync config, keeping the last good one: {e:#}");
+        }
+        self.client.set_retry_policy(self.config.retry_policy());
+        self.history.set_retention(self.config.retention());
+        self.scheduler
+            .reschedule(self.config.enabled_feeds(), self.config.interval());
+        self.config.take_conflicts()
+    }
 
-        // read on disk rss channel
-        if let Some(files) = &self.config.file() {
-            for file in files.iter() {
-                let file = File::open(file)?;
-                let from_file = Channel::read_from(BufReader::new(file))?;
+    /// Wait for `timeout`, following download status through aria2's notifications meanwhile.
+    ///
+    /// aria2 only notifies WebSocket clients, over HTTP this simply sleeps. Returns early when
+    /// the watched config changes, as feeds may be due earlier. A dry run only sleeps.
+    pub fn wait(&mut self, timeout: Duration, dry_run: bool) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut notifications = !dry_run;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            // Arni's own writes are noticed too
+            if self
+                .watcher
+                .as_ref()
+                .is_some_and(|watcher| watcher.changed())
+                && self.config.changed_on_disk()
+            {
+                info!("Config changed, reloading.");
+                // conflicts are logged when merging
+                self.sync_config(dry_run);
+                // a rejected edit is left on disk, keep waiting for a fix
+                if !self.config.changed_on_disk() {
+                    return Ok(());
+                }
+            }
+            let remaining = match self.watcher {
+                Some(_) => remaining.min(CONFIG_POLL),
+                None => remaining,
+            };
+            if !notifications {
+                std::thread::sleep(remaining);
+                continue;
+            }
+            match self
+                .client
+                .wait_notification(self.config.aria2_address(), remaining)
+            {
+                Ok(Some(notification)) => self.on_notification(notification)?,
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Can't receive notifications from aria2: {e}");
+                    notifications = false;
+                }
+            }
+        }
+    }
+
+    fn on_notification(&mut self, notification: Aria2Notification) -> Result<()> {
+        if !matches!(
+            notification.event,
+            Aria2Event::DownloadComplete | Aria2Event::DownloadError | Aria2Event::DownloadStop
+        ) {
+            return Ok(());
+        }
+        let tracked = self
+            .state
+            .download_list()
+            .iter()
+            .any(|epi| epi.gid.as_deref() == Some(notification.gid.as_str()));
+        if !tracked {
+            return Ok(());
+        }
+
+        info!(
+            "aria2 reports {:?} on {}, syncing download status",
+            notification.event, notification.gid
+        );
+        // failures are logged, there is no run to report them to
+        let mut report = RunReport::default();
+        self.sync_download_status(false, &mut report)?;
+        if self.requeue_errors(&mut report) {
+            self.send_waiting(&mut report)?;
+        }
+        self.update_history(&mut report);
+        self.history
+            .sync()
+            .inspect_err(|e| warn!("History sync failed: {e}"))?;
+        self.state
+            .sync()
+            .inspect_err(|e| warn!("State sync failed: {e}"))?;
+        Ok(())
+    }
 
-                ret.push(from_file);
+    /// Send every waiting episode in the download list to aria2 in one round trip.
+    fn send_waiting(&mut self, report: &mut RunReport) -> Result<()> {
+        info!("Sending episodes to aria2");
+        let calls = self
+            .state
+            .download_list()
+            .iter()
+            .filter(|epi| epi.is_waiting())
+            .map(|epi| self.add_uri(epi))
+            .collect::<Result<Vec<_>>>()?;
+        if calls.is_empty() {
+            info!("Nothing to send.");
+            return Ok(());
+        }
+        let results = self
+            .client
+            .send_multicall(self.config.aria2_address(), self.ua.as_str(), calls)
+            .inspect_err(|e| warn!("Fail to get JsonRPC's response: {e}"))?;
+        let waiting = self
+            .state
+            .download_list_mut()
+            .iter_mut()
+            .filter(|epi| epi.is_waiting());
+        for (epi, result) in waiting.zip(results) {
+            match result.result::<String>() {
+                Ok(gid) => {
+                    epi.gid = Some(gid);
+                    epi.set_sent();
+                    report.sent.push(epi.name().to_string());
+                }
+                Err(e) => {
+                    warn!("aria2 refused {}: {e}", epi.guid);
+                    report.fail(format!("episode {}", epi.name()), e);
+                }
             }
         }
+        Ok(())
+    }
 
-        // read web rss channel
-        if let Some(urls) = &self.config.url() {
-            for url in urls.iter() {
-                let response = self.client.inner().post(url).send()?;
-                let content = response.bytes()?;
-                let from_web = Channel::read_from(&content[..])?;
+    /// Print what [`App::send_waiting`] would send with `new` episodes added to the download
+    /// list, without touching it.
+    fn preview(&self, new: &[Episode], report: &mut RunReport) -> Result<()> {
+        let waiting = self
+            .state
+            .download_list()
+            .iter()
+            .filter(|epi| epi.is_waiting())
+            .chain(new);
+        let mut calls = vec![];
+        for epi in waiting {
+            calls.push(self.add_uri(epi)?);
+            report.sent.push(epi.name().to_string());
+        }
+        if calls.is_empty() {
+            info!("Nothing to send.");
+            return Ok(());
+        }
+        let jsonrpc = JsonRPCBuilder::new(self.ua.as_str())
+            .system_multicall(calls)
+            .build()?;
+        let response = self.client.dry_send(self.config.aria2_address(), jsonrpc)?;
+        println!("dry run: {}", response);
+        Ok(())
+    }
 
-                ret.push(from_web);
+    fn add_uri(&self, epi: &Episode) -> Result<JsonRPC> {
+        let mut options = self.config.aria2_options(epi.feed.as_deref());
+        if epi.retries > 0 {
+            let requeue = self.config.requeue_policy(epi.feed.as_deref());
+            options.extend(requeue.options.unwrap_or_default());
+        }
+        if let Some(dir) = &epi.dir {
+            options.insert("dir".to_string(), dir.to_string());
+        }
+        JsonRPCBuilder::new(self.ua.as_str())
+            .aria2_add_uri(
+                self.config.aria2_secret(),
+                &epi.torrent_link,
+                Some(&options),
+            )
+            .build()
+            .inspect_err(|e| warn!("Fail to build JsonRPC: {e}"))
+    }
+
+    /// Send episodes aria2 failed on again, or give up on them following the requeue policy.
+    /// Removed episodes are sent again as well if `on_removed` is `retry`, counting against the
+    /// same limit, so one cancelled in a frontend is not sent forever.
+    ///
+    /// Returns true if anything is waiting to be sent again.
+    fn requeue_errors(&mut self, report: &mut RunReport) -> bool {
+        let mut requeued = false;
+        let retry_removed = self.config.on_removed() == OnRemoved::Retry;
+        let to_requeue = |epi: &Episode| epi.is_error() || (retry_removed && epi.is_removed());
+        for epi in self
+            .state
+            .download_list_mut()
+            .iter_mut()
+            .filter(|epi| to_requeue(epi))
+        {
+            let policy = self.config.requeue_policy(epi.feed.as_deref());
+            let reason = if epi.is_removed() {
+                "being removed from aria2".to_string()
+            } else {
+                format!(
+                    "aria2 error {}: {}",
+                    epi.error_code.as_deref().unwrap_or("unknown"),
+                    epi.error_message.as_deref().unwrap_or_default()
+                )
+            };
+            if epi.retries < policy.max_retries {
+                // the same link again for removed ones, it was the user's call, not the link's
+                epi.requeue(epi.is_error() && policy.alternate_link);
+                info!(
+                    "Sending {} again ({}/{}) after {reason}",
+                    epi.guid, epi.retries, policy.max_retries
+                );
+                report.requeued.push(epi.name().to_string());
+                requeued = true;
+            } else if epi.is_removed() {
+                warn!("Giving up on {} after {reason}", epi.guid);
+                self.history.push(HistoryRecord::new(epi, Outcome::Removed));
+                report.fail(format!("episode {}", epi.name()), reason);
+            } else {
+                warn!("Giving up on {} after {reason}", epi.guid);
+                self.history.push(HistoryRecord::new(epi, Outcome::Failed));
+                report.fail(format!("episode {}", epi.name()), reason);
             }
         }
+        self.state
+            .download_list_mut()
+            .retain(|epi| !to_requeue(epi));
+        requeued
+    }
 
-        Ok(ret)
+    /// Move finished episodes from download list into history.
+    ///
+    /// Removed ones are recorded either way so they are not sent again, `on_removed = "record"`
+    /// reports them as well.
+    fn update_history(&mut self, report: &mut RunReport) {
+        let on_removed = self.config.on_removed();
+        for epi in self.state.download_list() {
+            if epi.is_completed() {
+                self.history
+                    .push(HistoryRecord::new(epi, Outcome::Completed));
+            } else if epi.is_removed() {
+                if on_removed == OnRemoved::Record {
+                    info!("{} has been removed from aria2, recording it.", epi.guid);
+                    report.removed.push(epi.name().to_string());
+                } else {
+                    debug!(
+                        "{} has been removed from aria2, not sending it again.",
+                        epi.guid
+                    );
+                }
+                self.history.push(HistoryRecord::new(epi, Outcome::Removed));
+            }
+        }
+
+        // remove items in download_list
+        self.state
+            .download_list_mut()
+            .retain(|epi| !epi.is_completed() && !epi.is_removed());
+    }
+
+    /// Check sent episodes loaded from state file against aria2.
+    fn reconcile(&mut self, report: &mut RunReport) -> Result<()> {
+        info!("Reconciling download list with aria2...");
+        self.sync_download_status(false, report)
+    }
+
+    /// Ask aria2 for the status of every sent episode in one round trip.
+    ///
+    /// Episodes aria2 no longer knows about are put back to waiting so they get sent again.
+    fn sync_download_status(&mut self, dry_run: bool, report: &mut RunReport) -> Result<()> {
+        let mut calls = vec![];
+        for epi in self
+            .state
+            .download_list()
+            .iter()
+            .filter(|epi| epi.is_sent())
+        {
+            let jsonrpc = JsonRPCBuilder::new(self.ua.as_str())
+                .aria2_tell_status(self.config.aria2_secret(), &epi.gid()?)
+                .build()
+                .inspect_err(|e| warn!("Fail to build JsonRPC: {e}"))?;
+            calls.push(jsonrpc);
+        }
+        if calls.is_empty() {
+            return Ok(());
+        }
+
+        if dry_run {
+            let jsonrpc = JsonRPCBuilder::new(self.ua.as_str())
+                .system_multicall(calls)
+                .build()?;
+            let response = self.client.dry_send(self.config.aria2_address(), jsonrpc)?;
+            println!("dry run: {}", response);
+            return Ok(());
+        }
+
+        let results = self
+            .client
+            .send_multicall(self.config.aria2_address(), self.ua.as_str(), calls)
+            .inspect_err(|e| warn!("Fail to get JsonRPC's response: {e}"))?;
+        let sent = self
+            .state
+            .download_list_mut()
+            .iter_mut()
+            .filter(|epi| epi.is_sent());
+        for (epi, result) in sent.zip(results) {
+            let status = match result.result::<Aria2Status>() {
+                Ok(status) => status,
+                Err(e) if matches!(e.downcast_ref(), Some(Error::Aria2GidNotFound)) => {
+                    warn!("aria2 lost track of {}, sending it again.", epi.guid);
+                    epi.set_waiting();
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Fail to get status of {}: {e}", epi.guid);
+                    report.fail(format!("episode {}", epi.name()), e);
+                    continue;
+                }
+            };
+            if let Err(e) = epi.update_status(&status) {
+                warn!("Fail to update status of {}: {e}", epi.guid);
+                report.fail(format!("episode {}", epi.name()), e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How long to wait before the next run in watch mode.
+    ///
+    /// Never longer than the global interval, so download status is still polled over HTTP.
+    pub fn until_next_run(&self) -> Duration {
+        let now = Utc::now();
+        let interval = self.config.interval();
+        match self.scheduler.next_due(self.config.enabled_feeds(), now) {
+            Some(next) => (next - now).to_std().unwrap_or_default().min(interval),
+            None => interval,
+        }
     }
 
-    fn check_aria2_connection(&mut self) -> bool {
+    /// Returns the fetched content of every enabled feed that is due along with the feed.
+    ///
+    /// Feeds failing to fetch are recorded in `report` and left out.
+    fn get_feeds(&mut self, report: &mut RunReport) -> Vec<(SerdeFeed, Feed)> {
+        let mut ret: Vec<(SerdeFeed, Feed)> = vec![];
+        let now = Utc::now();
+
+        let feeds: Vec<SerdeFeed> = self.config.enabled_feeds().cloned().collect();
+        for feed in feeds {
+            if !self.scheduler.is_due(&feed.name, now) {
+                debug!("Feed {} is not due yet.", feed.name);
+                continue;
+            }
+            // a feed failing to fetch waits for the next round as well
+            self.scheduler
+                .schedule(&feed, None, self.config.interval(), now);
+
+            let retry = self.client.retry_policy().clone();
+            let what = format!("fetching feed {}", feed.name);
+            let channel = match retry.retry(&what, || self.fetch_feed(&feed)) {
+                Ok(Some(channel)) => channel,
+                Ok(None) => {
+                    info!("Feed {} has not been modified, skipping.", feed.name);
+                    report.not_modified.push(feed.name.to_string());
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Fail to fetch feed {}: {e}", feed.name);
+                    report.fail(format!("feed {}", feed.name), e);
+                    continue;
+                }
+            };
+
+            self.scheduler
+                .schedule(&feed, channel.ttl, self.config.interval(), now);
+            report.fetched.push(feed.name.to_string());
+            ret.push((feed, channel));
+        }
+
+        ret
+    }
+
+    /// Read a feed, `None` if the web server reports it as not modified.
+    fn fetch_feed(&mut self, feed: &SerdeFeed) -> Result<Option<Feed>> {
+        let url = match &feed.source {
+            // read on disk feed
+            FeedSource::Path(path) => {
+                let content =
+                    std::fs::read(path).with_context(|| format!("Fail to read {path}"))?;
+                return Ok(Some(Feed::read_from(&content)?));
+            }
+            // read web feed
+            FeedSource::Url(url) => url,
+        };
+        let mut request = self.client.inner().get(url);
+        if let Some(validators) = self.cache.get(url) {
+            if let Some(etag) = &validators.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let response = request.send()?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let content = response.bytes()?;
+        let channel = Feed::read_from(&content)?;
+        self.validators.push((url.to_string(), validators));
+        Ok(Some(channel))
+    }
+
+    fn check_aria2_connection(&mut self) -> Result<(), Error> {
         info!("Checking connectin with aria2");
         let jsonrpc = JsonRPCBuilder::new(self.ua.as_str())
-            .aria2_get_version(None)
+            .aria2_get_version(self.config.aria2_secret())
             .build();
         let jsonrpc = match jsonrpc {
             Ok(jsonrpc) => jsonrpc,
             Err(_e) => {
                 error!("Can't build jsonrpc");
-                return false;
+                return Err(Error::Aria2ConnectionError);
             }
         };
         let response = self
             .client
             .send(self.config.aria2_address(), jsonrpc)
-            .map_err(|e| {
+            .inspect_err(|_e| {
                 error!("Can't get response from aria2.");
-                e
             });
         let response = match response {
             Ok(r) => r,
-            Err(_e) => return false,
+            Err(_e) => return Err(Error::Aria2ConnectionError),
+        };
+        let version = match response.result::<Aria2Version>() {
+            Ok(r) => r.version,
+            Err(e) => {
+                error!("aria2 refused the connection: {e}");
+                return match e.downcast::<Error>() {
+                    Ok(Error::Aria2Unauthorized) => Err(Error::Aria2Unauthorized),
+                    _ => Err(Error::Aria2ConnectionError),
+                };
+            }
         };
-        let response = response.unwrap_response().unwrap();
-        let version = response.get("version").unwrap();
         info!("Connection with aria2: {version}");
-        true
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        data::{history::History, state::State},
+        testing::{HttpServer, Response, TempDir},
+    };
+
+    const FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Releases</title>
+    <item>
+      <title>Show - 01</title>
+      <guid>ep1</guid>
+      <enclosure url="https://example.com/1.torrent" type="application/x-bittorrent"/>
+    </item>
+  </channel>
+</rss>"#;
+
+    /// Stands in for aria2, each call of a request or a multicall is answered by `call` with its
+    /// method and params, an `Err` being returned as a fault.
+    fn aria2(
+        mut call: impl FnMut(&str, &[Value]) -> Result<Value, String> + Send + 'static,
+    ) -> HttpServer {
+        let mut answer = move |method: &str, params: &[Value]| match method {
+            "aria2.getVersion" => Ok(json!({"version": "1.37.0"})),
+            _ => call(method, params),
+        };
+        HttpServer::new(move |request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let params = body["params"].as_array().cloned().unwrap_or_default();
+            let result = match body["method"].as_str().unwrap() {
+                "system.multicall" => {
+                    let results: Vec<_> = params[0]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|c| {
+                            let params = c["params"].as_array().cloned().unwrap_or_default();
+                            match answer(c["methodName"].as_str().unwrap(), &params) {
+                                Ok(result) => json!([result]),
+                                Err(e) => json!({"faultCode": 1, "faultString": e}),
+                            }
+                        })
+                        .collect();
+                    json!(results)
+                }
+                method => answer(method, &params).unwrap(),
+            };
+            let response = json!({"id": body["id"], "jsonrpc": "2.0", "result": result});
+            Response::new(200, response.to_string())
+        })
+    }
+
+    /// Methods aria2 was asked to run, with those in multicalls.
+    fn methods(aria2: &HttpServer) -> Vec<String> {
+        let mut ret = vec![];
+        for request in aria2.requests() {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            match body["method"].as_str().unwrap() {
+                "system.multicall" => {
+                    for c in body["params"][0].as_array().unwrap() {
+                        ret.push(c["methodName"].as_str().unwrap().to_string());
+                    }
+                }
+                method => ret.push(method.to_string()),
+            }
+        }
+        ret
+    }
+
+    /// aria2 accepting every download and reporting it with `status`.
+    fn aria2_with_status(status: &'static str) -> HttpServer {
+        let mut sent = 0;
+        aria2(move |method, params| match method {
+            "aria2.addUri" => {
+                sent += 1;
+                Ok(json!(format!("{sent:016x}")))
+            }
+            "aria2.tellStatus" => Ok(json!({"gid": params.last().unwrap(), "status": status})),
+            _ => Err(format!("unexpected {method}")),
+        })
+    }
+
+    fn write_config(dir: &TempDir, aria2: &HttpServer, extra: &str) {
+        std::fs::write(dir.join("feed.xml"), FEED).unwrap();
+        let source = format!("path = \"{}\"", dir.file("feed.xml"));
+        write_config_reading(dir, aria2, extra, &source);
+    }
+
+    /// Config with one feed `show` read from `source`, a `path = ...` or `url = ...` line.
+    fn write_config_reading(dir: &TempDir, aria2: &HttpServer, extra: &str, source: &str) {
+        let config = format!(
+            "aria2_address = \"{}\"\n{extra}\n\n[[feed]]\nname = \"show\"\n{source}\n",
+            aria2.url("/jsonrpc"),
+        );
+        std::fs::write(dir.join("config.toml"), config).unwrap();
+    }
+
+    /// One run of a fresh app on the files in `dir`, as a new process would do.
+    fn run_once(dir: &TempDir) -> Result<RunReport> {
+        let (config, history, state, cache) = (
+            dir.file("config.toml"),
+            dir.file("history.toml"),
+            dir.file("state.toml"),
+            dir.file("cache.toml"),
+        );
+        let mut config = Config::new(&config, false).unwrap();
+        let mut history = History::new(&history, false).unwrap();
+        let mut state = State::new(&state, false).unwrap();
+        let mut cache = FeedCache::new(&cache, false).unwrap();
+        let mut app = App::new(&mut config, &mut history, &mut state, &mut cache).unwrap();
+        app.run(false)
+    }
+
+    fn recorded(dir: &TempDir) -> Vec<(String, Outcome)> {
+        let path = dir.file("history.toml");
+        let history = History::new(&path, false).unwrap();
+        history
+            .records()
+            .map(|r| (r.guid.to_string(), r.status))
+            .collect()
+    }
+
+    fn sent(aria2: &HttpServer) -> usize {
+        methods(aria2)
+            .iter()
+            .filter(|m| *m == "aria2.addUri")
+            .count()
+    }
+
+    #[test]
+    fn removed_retry() {
+        let dir = TempDir::new("app-retry");
+        let aria2 = aria2_with_status("removed");
+        write_config(
+            &dir,
+            &aria2,
+            "on_removed = \"retry\"\n[requeue]\nmax_retries = 1",
+        );
+
+        let report = run_once(&dir).unwrap();
+        assert_eq!(report.requeued, vec!["Show - 01".to_string()]);
+        assert!(report.is_ok());
+        assert_eq!(sent(&aria2), 2);
+
+        // out of retries, given up on and never sent again
+        let report = run_once(&dir).unwrap();
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(recorded(&dir), vec![("ep1".to_string(), Outcome::Removed)]);
+        run_once(&dir).unwrap();
+        assert_eq!(sent(&aria2), 2);
+    }
+
+    #[test]
+    fn removed_ignore() {
+        let dir = TempDir::new("app-ignore");
+        let aria2 = aria2_with_status("removed");
+        write_config(&dir, &aria2, "on_removed = \"ignore\"");
+
+        let report = run_once(&dir).unwrap();
+        assert!(report.is_ok());
+        assert!(report.removed.is_empty());
+        assert_eq!(recorded(&dir), vec![("ep1".to_string(), Outcome::Removed)]);
+
+        // still in the feed, not sent again
+        let report = run_once(&dir).unwrap();
+        assert!(report.sent.is_empty());
+        assert_eq!(sent(&aria2), 1);
+    }
+
+    #[test]
+    fn removed_record() {
+        let dir = TempDir::new("app-record");
+        let aria2 = aria2_with_status("removed");
+        write_config(&dir, &aria2, "on_removed = \"record\"");
+
+        let report = run_once(&dir).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.removed, vec!["Show - 01".to_string()]);
+        assert_eq!(recorded(&dir), vec![("ep1".to_string(), Outcome::Removed)]);
+
+        let report = run_once(&dir).unwrap();
+        assert!(report.sent.is_empty());
+        assert_eq!(sent(&aria2), 1);
+    }
+
+    #[test]
+    fn mixed_multicall_results() {
+        let dir = TempDir::new("app-multicall");
+        // "b" is refused and the status of "a" can't be told, in between calls that succeed
+        let aria2 = aria2(|method, params| match method {
+            "aria2.addUri" => match params[1][0].as_str().unwrap() {
+                "b.torrent" => Err("bad uri".to_string()),
+                uri => Ok(json!(format!("gid-{}", uri.trim_end_matches(".torrent")))),
+            },
+            "aria2.tellStatus" => match params.last().unwrap().as_str().unwrap() {
+                "gid-a" => Err("boom".to_string()),
+                "gid-c" => Ok(json!({"gid": "gid-c", "status": "complete"})),
+                gid => Ok(json!({"gid": gid, "status": "active"})),
+            },
+            _ => Err(format!("unexpected {method}")),
+        });
+        write_config(&dir, &aria2, "");
+        // sent and waiting ones interleaved, so neither is a prefix of the download list
+        let path = dir.file("state.toml");
+        let mut state = State::new(&path, false).unwrap();
+        for (guid, sent) in [
+            ("a", true),
+            ("b", false),
+            ("c", true),
+            ("d", false),
+            ("e", true),
+        ] {
+            let mut epi = Episode::new(guid.to_string(), None, format!("{guid}.torrent"));
+            if sent {
+                epi.gid = Some(format!("gid-{guid}"));
+                epi.set_sent();
+            }
+            state.download_list_mut().push(epi);
+        }
+        SyncFile::sync(&mut state).unwrap();
+
+        let report = run_once(&dir).unwrap();
+        let failed: Vec<_> = report.failures.iter().map(|f| f.subject.as_str()).collect();
+        // "a" when reconciling and after sending
+        assert_eq!(failed, vec!["episode a", "episode b", "episode a"]);
+        assert_eq!(report.sent, vec!["d".to_string(), "Show - 01".to_string()]);
+
+        let state = State::new(&path, false).unwrap();
+        let list: Vec<_> = state
+            .download_list()
+            .iter()
+            .map(|epi| (epi.guid.as_str(), epi.gid.as_deref(), epi.is_sent()))
+            .collect();
+        assert_eq!(
+            list,
+            vec![
+                ("a", Some("gid-a"), true),
+                ("b", None, false),
+                ("d", Some("gid-d"), true),
+                ("e", Some("gid-e"), true),
+                ("ep1", Some("gid-https://example.com/1"), true),
+            ]
+        );
+        assert_eq!(recorded(&dir), vec![("c".to_string(), Outcome::Completed)]);
+    }
+
+    #[test]
+    fn conditional_fetch() {
+        let dir = TempDir::new("app-fetch");
+        let feed = HttpServer::new(|request| {
+            if request.header("if-none-match") == Some("\"v1\"") {
+                return Response::new(304, "");
+            }
+            Response::new(200, FEED)
+                .header("ETag", "\"v1\"")
+                .header("Last-Modified", "Sat, 07 Oct 2023 10:00:00 GMT")
+        });
+        let url = feed.url("/feed.xml");
+        // aria2 going away after the connection check, before anything is sent
+        let down = HttpServer::new(|request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            match body["method"].as_str().unwrap() {
+                "aria2.getVersion" => {
+                    let result = json!({"version": "1.37.0"});
+                    let response = json!({"id": body["id"], "jsonrpc": "2.0", "result": result});
+                    Response::new(200, response.to_string())
+                }
+                _ => Response::new(500, ""),
+            }
+        });
+        write_config_reading(&dir, &down, "", &format!("url = \"{url}\""));
+        let validators = || {
+            let path = dir.file("cache.toml");
+            FeedCache::new(&path, false).unwrap().get(&url).cloned()
+        };
+
+        // fetched, but the episode was never saved, so the next run must not get 304
+        assert!(run_once(&dir).is_err());
+        assert_eq!(validators(), None);
+
+        let aria2 = aria2_with_status("active");
+        write_config_reading(&dir, &aria2, "", &format!("url = \"{url}\""));
+        let report = run_once(&dir).unwrap();
+        assert_eq!(report.fetched, vec!["show".to_string()]);
+        assert_eq!(report.sent, vec!["Show - 01".to_string()]);
+        let cached = validators().unwrap();
+        assert_eq!(cached.etag.as_deref(), Some("\"v1\""));
+
+        let report = run_once(&dir).unwrap();
+        assert_eq!(report.not_modified, vec!["show".to_string()]);
+        assert!(report.sent.is_empty());
+
+        let requests = feed.requests();
+        assert_eq!(requests.len(), 3);
+        for request in &requests[..2] {
+            assert_eq!(request.header("if-none-match"), None);
+            assert_eq!(request.header("if-modified-since"), None);
+        }
+        assert_eq!(requests[2].header("if-none-match"), Some("\"v1\""));
+        assert_eq!(
+            requests[2].header("if-modified-since"),
+            Some("Sat, 07 Oct 2023 10:00:00 GMT")
+        );
     }
 }
@@ -0,0 +1,137 @@
+use std::fmt::Display;
+
+/// What happened in one run, failures of one feed or item don't stop the others.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunReport {
+    /// Names of feeds fetched and parsed
+    pub fetched: Vec<String>,
+    /// Names of web feeds the server reported as not modified
+    pub not_modified: Vec<String>,
+    pub skipped: Vec<Skipped>,
+    /// Episodes accepted by aria2, or printed in dry run
+    pub sent: Vec<String>,
+    /// Episodes aria2 failed on, which are sent again
+    pub requeued: Vec<String>,
+    /// Episodes removed from aria2 before finishing and recorded as such, see `on_removed`
+    pub removed: Vec<String>,
+    pub failures: Vec<Failure>,
+    /// Config keys edited on disk while changed in Arni too, the values on disk were kept
+    pub conflicts: Vec<String>,
+}
+
+/// A feed item that could not be turned into a download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    pub feed: String,
+    /// Title of the item, or its guid
+    pub item: String,
+    pub reason: String,
+}
+
+/// Something failed for a feed or an episode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// e.g. `feed show` or `episode [Sub] Show - 01`
+    pub subject: String,
+    pub reason: String,
+}
+
+impl RunReport {
+    pub fn skip(&mut self, feed: &str, item: &str, reason: impl Display) {
+        self.skipped.push(Skipped {
+            feed: feed.to_string(),
+            item: item.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn fail(&mut self, subject: impl Into<String>, reason: impl Display) {
+        self.failures.push(Failure {
+            subject: subject.into(),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for RunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Run report:")?;
+        writeln!(
+            f,
+            "  fetched {} feeds: {}",
+            self.fetched.len(),
+            self.fetched.join(", ")
+        )?;
+        if !self.not_modified.is_empty() {
+            writeln!(f, "  not modified: {}", self.not_modified.join(", "))?;
+        }
+        writeln!(f, "  sent {} episodes", self.sent.len())?;
+        for name in &self.sent {
+            writeln!(f, "    - {name}")?;
+        }
+        if !self.requeued.is_empty() {
+            writeln!(f, "  requeued {} episodes", self.requeued.len())?;
+            for name in &self.requeued {
+                writeln!(f, "    - {name}")?;
+            }
+        }
+        if !self.removed.is_empty() {
+            writeln!(f, "  removed from aria2 {} episodes", self.removed.len())?;
+            for name in &self.removed {
+                writeln!(f, "    - {name}")?;
+            }
+        }
+        if !self.skipped.is_empty() {
+            writeln!(f, "  skipped {} items", self.skipped.len())?;
+            for skipped in &self.skipped {
+                writeln!(
+                    f,
+                    "    - [{}] {}: {}",
+                    skipped.feed, skipped.item, skipped.reason
+                )?;
+            }
+        }
+        if !self.failures.is_empty() {
+            writeln!(f, "  {} failures", self.failures.len())?;
+            for failure in &self.failures {
+                writeln!(f, "    - {}: {}", failure.subject, failure.reason)?;
+            }
+        }
+        if !self.conflicts.is_empty() {
+            writeln!(
+                f,
+                "  config conflicts, kept the values on disk: {}",
+                self.conflicts.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_report() {
+        let mut report = RunReport {
+            fetched: vec!["a".to_string(), "b".to_string()],
+            sent: vec!["Show - 01".to_string()],
+            ..Default::default()
+        };
+        assert!(report.is_ok());
+        report.skip("a", "Show - 02", "no download link");
+        report.fail("feed c", "connection refused");
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.to_string(),
+            "Run report:\n  fetched 2 feeds: a, b\n  sent 1 episodes\n    - Show - 01\n  \
+             skipped 1 items\n    - [a] Show - 02: no download link\n  \
+             1 failures\n    - feed c: connection refused\n"
+        );
+    }
+}
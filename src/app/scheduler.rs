@@ -0,0 +1,168 @@
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use chrono::{DateTime, Local, Utc};
+use cron::Schedule;
+use log::{debug, warn};
+
+use crate::data::config::SerdeFeed;
+
+/// Keeps track of when each feed should be fetched next.
+///
+/// Feeds that have never been fetched are always due.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    next: HashMap<String, DateTime<Utc>>,
+    /// When each feed was last fetched and the channel's `ttl`, to plan again on config changes
+    fetched: HashMap<String, (DateTime<Utc>, Option<u64>)>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_due(&self, feed: &str, now: DateTime<Utc>) -> bool {
+        match self.next.get(feed) {
+            Some(next) => *next <= now,
+            None => true,
+        }
+    }
+
+    /// Plan the next fetch of a feed that has just been fetched at `now`.
+    ///
+    /// A cron `schedule` wins over intervals. Otherwise the feed's `interval`, or `default` if
+    /// not set, is used, but never shorter than the channel's `ttl` in minutes.
+    pub fn schedule(
+        &mut self,
+        feed: &SerdeFeed,
+        ttl: Option<u64>,
+        default: Duration,
+        now: DateTime<Utc>,
+    ) {
+        let next = match feed
+            .schedule
+            .as_deref()
+            .and_then(|s| Self::next_cron(s, now))
+        {
+            Some(next) => next,
+            None => {
+                let interval = feed.interval.map(Duration::from_secs).unwrap_or(default);
+                let ttl = Duration::from_secs(ttl.unwrap_or(0) * 60);
+                now + interval.max(ttl)
+            }
+        };
+        debug!("Next fetch of feed {}: {next}", feed.name);
+        self.next.insert(feed.name.to_string(), next);
+        self.fetched.insert(feed.name.to_string(), (now, ttl));
+    }
+
+    /// Plan again after config changes.
+    ///
+    /// Feeds not in `feeds` are forgotten, the others are planned from their last fetch under
+    /// their current settings. New feeds stay due.
+    pub fn reschedule<'a>(
+        &mut self,
+        feeds: impl Iterator<Item = &'a SerdeFeed>,
+        default: Duration,
+    ) {
+        let feeds: Vec<_> = feeds.collect();
+        let known = |name: &String| feeds.iter().any(|feed| &feed.name == name);
+        self.next.retain(|name, _| known(name));
+        self.fetched.retain(|name, _| known(name));
+        for feed in &feeds {
+            if let Some((at, ttl)) = self.fetched.get(&feed.name).copied() {
+                self.schedule(feed, ttl, default, at);
+            }
+        }
+    }
+
+    /// Earliest time any of `feeds` is due, `None` if there is no feed at all.
+    pub fn next_due<'a>(
+        &self,
+        feeds: impl Iterator<Item = &'a SerdeFeed>,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        feeds
+            .map(|feed| self.next.get(&feed.name).copied().unwrap_or(now))
+            .min()
+    }
+
+    fn next_cron(schedule: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let schedule = Schedule::from_str(schedule)
+            .inspect_err(|e| warn!("Bad schedule \"{schedule}\", falling back to interval: {e}"))
+            .ok()?;
+        let next = schedule.after(&now.with_timezone(&Local)).next()?;
+        Some(next.with_timezone(&Utc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::config::FeedSource;
+
+    fn feed(name: &str) -> SerdeFeed {
+        SerdeFeed::new(
+            name.to_string(),
+            FeedSource::Url("https://example.com/rss".to_string()),
+        )
+    }
+
+    #[test]
+    fn interval_and_ttl() {
+        let now = Utc::now();
+        let hour = Duration::from_secs(3600);
+        let mut scheduler = Scheduler::new();
+        let mut a = feed("a");
+        a.interval = Some(600);
+        let b = feed("b");
+        assert!(scheduler.is_due("a", now));
+        assert_eq!(scheduler.next_due([&a, &b].into_iter(), now), Some(now));
+        assert_eq!(scheduler.next_due([].into_iter(), now), None);
+
+        scheduler.schedule(&a, None, hour, now);
+        scheduler.schedule(&b, Some(120), hour, now);
+        assert!(!scheduler.is_due("a", now));
+        assert!(scheduler.is_due("a", now + Duration::from_secs(600)));
+        assert!(!scheduler.is_due("b", now + Duration::from_secs(3600)));
+        assert!(scheduler.is_due("b", now + Duration::from_secs(7200)));
+        assert_eq!(
+            scheduler.next_due([&a, &b].into_iter(), now),
+            Some(now + Duration::from_secs(600))
+        );
+    }
+
+    #[test]
+    fn cron_schedule() {
+        let now = Utc::now();
+        let mut scheduler = Scheduler::new();
+        let mut a = feed("a");
+        // every minute at second 0
+        a.schedule = Some("0 * * * * *".to_string());
+        scheduler.schedule(&a, None, Duration::from_secs(3600), now);
+        assert!(!scheduler.is_due("a", now));
+        assert!(scheduler.is_due("a", now + Duration::from_secs(60)));
+
+        a.schedule = Some("not cron".to_string());
+        scheduler.schedule(&a, None, Duration::from_secs(3600), now);
+        assert!(!scheduler.is_due("a", now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn reschedule_on_config_change() {
+        let now = Utc::now();
+        let hour = Duration::from_secs(3600);
+        let mut scheduler = Scheduler::new();
+        let mut a = feed("a");
+        let b = feed("b");
+        scheduler.schedule(&a, None, hour, now);
+        scheduler.schedule(&b, None, hour, now);
+        assert!(!scheduler.is_due("a", now + Duration::from_secs(600)));
+
+        a.interval = Some(600);
+        scheduler.reschedule([&a].into_iter(), hour);
+        assert!(scheduler.is_due("a", now + Duration::from_secs(600)));
+        // b is removed, and due again if it comes back
+        assert!(scheduler.is_due("b", now));
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    ffi::OsString,
+    path::Path,
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use anyhow::Result;
+use log::{debug, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Editors write a file in several steps, changes this close together are taken as one.
+const SETTLE: Duration = Duration::from_millis(200);
+
+/// Watches the config file in watch mode so edits are applied right away.
+///
+/// The directory is watched rather than the file, as editors often save by replacing the file,
+/// and so does Arni itself.
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+    rx: Receiver<()>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path) -> Result<Self> {
+        let name = path.file_name().map(OsString::from).unwrap_or_default();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Error watching config: {e}");
+                    return;
+                }
+            };
+            if matches!(event.kind, EventKind::Access(_))
+                || !event.paths.iter().any(|p| p.file_name() == Some(&name))
+            {
+                return;
+            }
+            debug!("Config changed: {event:?}");
+            let _ = tx.send(());
+        })?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        Ok(Self {
+            _watcher: watcher,
+            rx,
+        })
+    }
+
+    /// Whether the file changed since the last call, waiting for a burst of changes to settle.
+    pub fn changed(&self) -> bool {
+        if self.rx.try_recv().is_err() {
+            return false;
+        }
+        while self.rx.recv_timeout(SETTLE).is_ok() {}
+        true
+    }
+}
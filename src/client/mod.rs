@@ -0,0 +1,185 @@
+use std::{io::Read, time::Duration};
+
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::jsonrpc::{
+    types::{Aria2Notification, MulticallResult},
+    JsonRPC, JsonRPCBuilder, JsonRPCError, JsonRPCResponse,
+};
+
+pub mod retry;
+pub mod ws;
+
+use retry::RetryPolicy;
+use ws::WsTransport;
+
+pub struct UA {
+    inner: String,
+}
+
+impl UA {
+    pub fn new(ua: &str) -> Self {
+        Self {
+            inner: ua.to_string(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.inner
+    }
+
+    pub fn into_string(self) -> String {
+        self.inner
+    }
+}
+
+impl Default for UA {
+    fn default() -> Self {
+        Self {
+            inner: concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_string(),
+        }
+    }
+}
+
+/// Talks to aria2 through HTTP, or WebSocket if the address starts with `ws://` or `wss://`.
+pub struct Client {
+    client: reqwest::blocking::Client,
+    ws: Option<WsTransport>,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    pub fn new() -> Result<Self> {
+        Self::with_ua(&UA::default())
+    }
+
+    pub fn with_ua(ua: &UA) -> Result<Self> {
+        let client = reqwest::blocking::Client::builder()
+            .user_agent(ua.as_str())
+            .build()?;
+        Ok(Self {
+            client,
+            ws: None,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    pub fn inner(&self) -> &reqwest::blocking::Client {
+        &self.client
+    }
+
+    pub fn inner_mut(&mut self) -> &mut reqwest::blocking::Client {
+        &mut self.client
+    }
+
+    pub fn dry_send(&self, _address: &str, jsonrpc: JsonRPC) -> Result<String> {
+        let _method = jsonrpc.get_method()?;
+        let jsonrpc = jsonrpc.to_string()?;
+        Ok(jsonrpc)
+    }
+
+    pub fn is_websocket(address: &str) -> bool {
+        address.starts_with("ws://") || address.starts_with("wss://")
+    }
+
+    /// Send a request to aria2, transient failures are retried following the retry policy.
+    ///
+    /// Requests adding downloads are only retried if the connection failed, aria2 may have
+    /// queued them already when a response is lost.
+    pub fn send(&mut self, address: &str, jsonrpc: JsonRPC) -> Result<JsonRPCResponse> {
+        let method = jsonrpc.get_method()?;
+        let adds = jsonrpc.adds_downloads();
+        let jsonrpc = jsonrpc.to_string()?;
+        let retry = self.retry.clone();
+        let send = || self.send_once(address, &jsonrpc);
+        let value = if adds {
+            retry.retry_if(method.as_str(), RetryPolicy::is_connect_error, send)?
+        } else {
+            retry.retry(method.as_str(), send)?
+        };
+        Ok(JsonRPCResponse { value, method })
+    }
+
+    fn send_once(&mut self, address: &str, jsonrpc: &str) -> Result<serde_json::Value> {
+        if Self::is_websocket(address) {
+            return self
+                .ws(address)?
+                .send(jsonrpc.to_string())
+                .inspect_err(|_e| {
+                    // reconnect next time
+                    self.ws = None;
+                });
+        }
+        let mut response = self.client.post(address).body(jsonrpc.to_string()).send()?;
+        // aria2 answers rpc errors with 400, only gateway errors and alike are worth a retry
+        if self.retry.is_retryable_status(response.status()) {
+            response = response.error_for_status()?;
+        }
+        let mut response_value = String::new();
+        response.read_to_string(&mut response_value)?;
+        let response_value: serde_json::Value = serde_json::from_str(&response_value)?;
+        Ok(response_value)
+    }
+
+    /// Send `calls` in one `system.multicall` request.
+    ///
+    /// Results are in the same order as `calls`, each of them may succeed or fail on its own.
+    pub fn send_multicall(
+        &mut self,
+        address: &str,
+        ua: &str,
+        calls: Vec<JsonRPC>,
+    ) -> Result<Vec<MulticallResult>> {
+        let len = calls.len();
+        let jsonrpc = JsonRPCBuilder::new(ua).system_multicall(calls).build()?;
+        let results = self
+            .send(address, jsonrpc)?
+            .result::<Vec<MulticallResult>>()?;
+        if results.len() != len {
+            return Err(JsonRPCError::NotStandardResponse.into());
+        }
+        Ok(results)
+    }
+
+    /// Wait for the next notification from aria2, returns `None` on timeout.
+    ///
+    /// aria2 only pushes notifications through WebSocket, over HTTP this just sleeps.
+    pub fn wait_notification(
+        &mut self,
+        address: &str,
+        timeout: Duration,
+    ) -> Result<Option<Aria2Notification>> {
+        if !Self::is_websocket(address) {
+            std::thread::sleep(timeout);
+            return Ok(None);
+        }
+        self.ws(address)?
+            .wait_notification(timeout)
+            .inspect_err(|e| {
+                warn!("WebSocket connection lost: {e}");
+                self.ws = None;
+            })
+    }
+
+    /// The connection to `address`, reconnecting if there is none or it goes elsewhere, as the
+    /// address may change with a config reload.
+    fn ws(&mut self, address: &str) -> Result<&mut WsTransport> {
+        let ws = match self.ws.take() {
+            Some(ws) if ws.address() == address => ws,
+            _ => {
+                info!("Connecting to aria2 through WebSocket...");
+                WsTransport::connect(address)?
+            }
+        };
+        Ok(self.ws.insert(ws))
+    }
+}
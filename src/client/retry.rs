@@ -0,0 +1,240 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::ErrorKind,
+    time::Duration,
+};
+
+use anyhow::Result;
+use log::warn;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+/// Longest wait between attempts, whatever the policy says
+const MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// How transient failures of feed fetches and aria2 rpc calls are retried, the `[retry]` table
+/// in config.
+///
+/// Only connection level errors and the listed status codes are retried, any other failure is
+/// returned right away.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one
+    pub max_attempts: u32,
+    /// Seconds to wait before the first retry, doubled on each following one
+    pub backoff: f64,
+    /// Upper bound of the wait in seconds
+    pub max_backoff: f64,
+    /// Randomize each wait by up to this fraction, e.g. `0.2` for ±20%
+    pub jitter: f64,
+    /// HTTP status codes worth another attempt
+    pub retry_status: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: 1.0,
+            max_backoff: 60.0,
+            jitter: 0.2,
+            retry_status: vec![408, 429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run `f` until it succeeds, fails with an error not worth retrying, or attempts run out.
+    pub fn retry<T>(&self, what: &str, f: impl FnMut() -> Result<T>) -> Result<T> {
+        self.retry_if(what, |e| self.is_retryable(e), f)
+    }
+
+    /// Like [`RetryPolicy::retry`], but only errors `retryable` accepts are retried.
+    pub fn retry_if<T>(
+        &self,
+        what: &str,
+        retryable: impl Fn(&anyhow::Error) -> bool,
+        mut f: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Ok(ret) => return Ok(ret),
+                Err(e) if attempt < self.max_attempts && retryable(&e) => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "Attempt {attempt}/{} of {what} failed, retrying in {:.1}s: {e}",
+                        self.max_attempts,
+                        delay.as_secs_f64()
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retry_status.contains(&status.as_u16())
+    }
+
+    /// Whether anything in the error chain is a transient transport error or a listed status.
+    pub fn is_retryable(&self, e: &anyhow::Error) -> bool {
+        e.chain().any(|e| {
+            if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+                return match e.status() {
+                    Some(status) => self.is_retryable_status(status),
+                    None => e.is_connect() || e.is_timeout() || e.is_request(),
+                };
+            }
+            if let Some(e) = e.downcast_ref::<tungstenite::Error>() {
+                return match e {
+                    tungstenite::Error::Io(e) => is_transient(e.kind()),
+                    tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+                        true
+                    }
+                    tungstenite::Error::Http(response) => {
+                        self.retry_status.contains(&response.status().as_u16())
+                    }
+                    _ => false,
+                };
+            }
+            if let Some(e) = e.downcast_ref::<std::io::Error>() {
+                return is_transient(e.kind());
+            }
+            false
+        })
+    }
+
+    /// Whether the error happened while connecting, so the request surely never reached the
+    /// server.
+    pub fn is_connect_error(e: &anyhow::Error) -> bool {
+        e.chain().any(|e| {
+            if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+                return e.is_connect();
+            }
+            if let Some(tungstenite::Error::Io(e)) = e.downcast_ref::<tungstenite::Error>() {
+                return e.kind() == ErrorKind::ConnectionRefused;
+            }
+            if let Some(e) = e.downcast_ref::<std::io::Error>() {
+                return e.kind() == ErrorKind::ConnectionRefused;
+            }
+            false
+        })
+    }
+
+    /// Wait before the retry following `attempt`, counting from 1.
+    fn delay(&self, attempt: u32) -> Duration {
+        let exp = self.backoff * 2f64.powi(attempt.saturating_sub(1) as i32);
+        let base = exp.min(self.max_backoff).max(0.0);
+        // uniform in [-1, 1]
+        let r = random() as f64 / u64::MAX as f64 * 2.0 - 1.0;
+        // backoff comes from config, which may well say `inf` or `1e30`
+        let secs = (base * (1.0 + self.jitter * r)).max(0.0);
+        Duration::from_secs_f64(secs.min(MAX_DELAY.as_secs_f64()))
+    }
+}
+
+fn is_transient(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+/// Good enough randomness for jitter, std seeds each `RandomState` randomly.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fast() -> RetryPolicy {
+        RetryPolicy {
+            backoff: 0.001,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn retry_transient_errors() {
+        let mut attempts = 0;
+        let ret = fast().retry("test", || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(std::io::Error::from(ErrorKind::ConnectionRefused).into())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(ret.unwrap(), 3);
+
+        let mut attempts = 0;
+        let ret: Result<()> = fast().retry("test", || {
+            attempts += 1;
+            Err(std::io::Error::from(ErrorKind::ConnectionReset).into())
+        });
+        assert!(ret.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn fail_fast_on_other_errors() {
+        let mut attempts = 0;
+        let ret: Result<()> = fast().retry("test", || {
+            attempts += 1;
+            Err(std::io::Error::from(ErrorKind::PermissionDenied).into())
+        });
+        assert!(ret.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn retry_only_connect_errors() {
+        let mut attempts = 0;
+        let ret: Result<()> = fast().retry_if("test", RetryPolicy::is_connect_error, || {
+            attempts += 1;
+            if attempts < 2 {
+                Err(std::io::Error::from(ErrorKind::ConnectionRefused).into())
+            } else {
+                Err(std::io::Error::from(ErrorKind::TimedOut).into())
+            }
+        });
+        assert!(ret.is_err());
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn exponential_delay() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            max_backoff: 3.0,
+            ..Default::default()
+        };
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(3));
+
+        let policy = RetryPolicy::default();
+        let delay = policy.delay(1).as_secs_f64();
+        assert!((0.8..=1.2).contains(&delay));
+
+        let policy = RetryPolicy {
+            backoff: 1e30,
+            max_backoff: f64::INFINITY,
+            ..Default::default()
+        };
+        assert_eq!(policy.delay(1), MAX_DELAY);
+    }
+}
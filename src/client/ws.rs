@@ -0,0 +1,172 @@
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::{debug, warn};
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+use crate::jsonrpc::{types::Aria2Notification, JsonRPCError};
+
+/// How long to wait for a response, the same as the HTTP client's default timeout
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A WebSocket connection to aria2's rpc endpoint.
+///
+/// aria2 pushes notifications through the same connection, those arriving while we wait for a
+/// response are kept until [`WsTransport::wait_notification`] is called.
+pub struct WsTransport {
+    address: String,
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    notifications: VecDeque<Aria2Notification>,
+}
+
+impl WsTransport {
+    pub fn connect(address: &str) -> Result<Self> {
+        let (socket, _) = tungstenite::connect(address)?;
+        Ok(Self {
+            address: address.to_string(),
+            socket,
+            notifications: VecDeque::new(),
+        })
+    }
+
+    /// The address connected to.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Send a request and wait for its response, failing with [`ErrorKind::TimedOut`] if none
+    /// arrives within [`RESPONSE_TIMEOUT`].
+    pub fn send(&mut self, jsonrpc: String) -> Result<serde_json::Value> {
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        self.socket.send(Message::Text(jsonrpc))?;
+        loop {
+            // notifications arriving meanwhile must not keep us waiting past the deadline
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(std::io::Error::from(ErrorKind::TimedOut).into());
+            }
+            self.set_read_timeout(Some(deadline - now))?;
+            let value = match self.read() {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(e) if is_timeout(&e) => {
+                    return Err(std::io::Error::from(ErrorKind::TimedOut).into())
+                }
+                Err(e) => return Err(e),
+            };
+            // responses never carry a method
+            if value.get("method").is_none() {
+                return Ok(value);
+            }
+            self.push_notification(value);
+        }
+    }
+
+    /// Wait for the next notification from aria2, returns `None` on timeout.
+    pub fn wait_notification(&mut self, timeout: Duration) -> Result<Option<Aria2Notification>> {
+        let deadline = Instant::now() + timeout;
+        while self.notifications.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            self.set_read_timeout(Some(deadline - now))?;
+            match self.read() {
+                Ok(Some(value)) => self.push_notification(value),
+                Ok(None) => {}
+                Err(e) if is_timeout(&e) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(self.notifications.pop_front())
+    }
+
+    /// Read one text message, other kinds of messages are skipped as `None`.
+    fn read(&mut self) -> Result<Option<serde_json::Value>> {
+        match self.socket.read()? {
+            Message::Text(text) => Ok(Some(serde_json::from_str(&text)?)),
+            Message::Close(_) => Err(JsonRPCError::NotStandardResponse.into()),
+            _ => Ok(None),
+        }
+    }
+
+    fn push_notification(&mut self, value: serde_json::Value) {
+        match serde_json::from_value::<Aria2Notification>(value) {
+            Ok(notification) => {
+                debug!("Notification from aria2: {notification:?}");
+                self.notifications.push_back(notification);
+            }
+            Err(e) => warn!("Unknown message from aria2: {e}"),
+        }
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        match self.socket.get_mut() {
+            MaybeTlsStream::Plain(stream) => stream.set_read_timeout(timeout)?,
+            MaybeTlsStream::NativeTls(stream) => stream.get_mut().set_read_timeout(timeout)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn is_timeout(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<tungstenite::Error>() {
+        Some(tungstenite::Error::Io(e)) => {
+            matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::jsonrpc::types::Aria2Event;
+
+    #[test]
+    fn response_and_notifications() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("ws://{}/jsonrpc", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = tungstenite::accept(stream).unwrap();
+            let request = socket.read().unwrap();
+            assert!(request.to_text().unwrap().contains("aria2.getVersion"));
+            let notification = r#"{"jsonrpc":"2.0","method":"aria2.onDownloadComplete","params":[{"gid":"2089b05ecca3d829"}]}"#;
+            socket.send(Message::text(notification)).unwrap();
+            let response = r#"{"id":"arni","jsonrpc":"2.0","result":{"version":"1.37.0"}}"#;
+            socket.send(Message::text(response)).unwrap();
+            let notification = r#"{"jsonrpc":"2.0","method":"aria2.onDownloadError","params":[{"gid":"d2703803b52216d1"}]}"#;
+            socket.send(Message::text(notification)).unwrap();
+            // keep the connection open until the client is done
+            let _ = socket.read();
+        });
+
+        let mut transport = WsTransport::connect(&address).unwrap();
+        let request = r#"{"jsonrpc":"2.0","method":"aria2.getVersion","id":"arni","params":[]}"#;
+        let response = transport.send(request.to_string()).unwrap();
+        assert_eq!(response["result"]["version"], "1.37.0");
+
+        let timeout = Duration::from_secs(5);
+        let notification = transport.wait_notification(timeout).unwrap().unwrap();
+        assert_eq!(notification.event, Aria2Event::DownloadComplete);
+        assert_eq!(notification.gid, "2089b05ecca3d829");
+        let notification = transport.wait_notification(timeout).unwrap().unwrap();
+        assert_eq!(notification.event, Aria2Event::DownloadError);
+        let notification = transport
+            .wait_notification(Duration::from_millis(100))
+            .unwrap();
+        assert!(notification.is_none());
+
+        transport.socket.close(None).unwrap();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+use std::{collections::BTreeMap, fs::File, io, path::Path};
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{file::write_atomic, SyncFile};
+
+/// HTTP validators of web feeds, used to skip feeds that haven't changed since the last fetch.
+pub struct FeedCache<'a> {
+    /// Contents as of the last sync
+    snapshot: String,
+    path: &'a Path,
+    inner: SerdeFeedCache,
+}
+
+impl<'a> FeedCache<'a> {
+    /// With `dry_run` nothing is written, a missing file is only assumed to be the default.
+    pub fn new(path: &'a str, dry_run: bool) -> Result<Self> {
+        let path = Path::new(path);
+        let (inner, snapshot) = if path.exists() {
+            let file = File::open(path).with_context(|| "Fail to open path.")?;
+            let file =
+                io::read_to_string(file).with_context(|| "Fail to read on disk cache file.")?;
+            let ret = toml::from_str(&file).with_context(|| "Fail to parse cache file.")?;
+            (ret, file)
+        } else {
+            let ret = SerdeFeedCache::default();
+            let file = toml::to_string_pretty(&ret)?;
+            if !dry_run {
+                write_atomic(path, file.as_bytes())
+                    .with_context(|| "Fail to create cache file.")?;
+            }
+            (ret, file)
+        };
+
+        Ok(Self {
+            snapshot,
+            path,
+            inner,
+        })
+    }
+
+    pub fn get(&self, url: &str) -> Option<&Validators> {
+        self.inner.feed.get(url)
+    }
+
+    pub fn insert(&mut self, url: &str, validators: Validators) {
+        if validators.etag.is_none() && validators.last_modified.is_none() {
+            self.inner.feed.remove(url);
+        } else {
+            self.inner.feed.insert(url.to_string(), validators);
+        }
+    }
+}
+
+impl SyncFile for FeedCache<'_> {
+    fn path(&self) -> &Path {
+        self.path
+    }
+
+    fn snapshot(&self) -> &str {
+        &self.snapshot
+    }
+
+    fn set_snapshot(&mut self, contents: String) {
+        self.snapshot = contents;
+    }
+
+    fn merge(&mut self, _base: &str, _on_disk: &str) -> Result<()> {
+        warn!("Cache file has been modified outside Arni, overwriting it.");
+        Ok(())
+    }
+
+    fn serialize(&mut self) -> Result<String> {
+        Ok(toml::to_string_pretty(&self.inner)?)
+    }
+}
+
+/// `ETag` and `Last-Modified` of the last response of a feed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SerdeFeedCache {
+    /// Keyed by feed url
+    feed: BTreeMap<String, Validators>,
+}
@@ -0,0 +1,542 @@
+//! Validation of config, with diagnostics pointing at the offending line and column.
+//!
+//! Problems are found on the parsed [`SerdeConfig`] and addressed by their key path, which is
+//! then looked up in a tree of spans parsed from the same text.
+
+use std::{collections::BTreeMap, fmt::Display, path::Path, str::FromStr};
+
+use anyhow::{bail, Result};
+use cron::Schedule;
+use regex::Regex;
+use reqwest::Url;
+use serde::{
+    de::{MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use toml::Spanned;
+
+use super::{
+    config::{FeedSource, SerdeConfig, ARIA2_SECRET_ENV},
+    template,
+};
+
+/// Schemes aria2 listens on for rpc
+const ARIA2_SCHEMES: [&str; 4] = ["http", "https", "ws", "wss"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// Works, but probably not as intended
+    Warning,
+}
+
+/// One problem found in config, lines and columns count from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: usize,
+    pub column: usize,
+    /// Key path like `feed[0].filter.include[1]`, empty for the file as a whole
+    pub key: String,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}:{}: {severity}: ", self.line, self.column)?;
+        if !self.key.is_empty() {
+            write!(f, "{}: ", self.key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Check config text, parse errors included.
+pub fn check(text: &str) -> Vec<Diagnostic> {
+    let config = match toml::from_str::<SerdeConfig>(text) {
+        Ok(config) => config,
+        Err(e) => {
+            let (line, column) = e
+                .span()
+                .map(|span| line_column(text, span.start))
+                .unwrap_or((1, 1));
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                line,
+                column,
+                key: String::new(),
+                message: e.message().to_string(),
+            }];
+        }
+    };
+    let spans = toml::from_str::<Node>(text).unwrap_or(Node::Leaf);
+    let mut problems = problems(&config);
+    // whatever survives a round trip is known, the rest is ignored when loading
+    if let Ok(known) = toml::Value::try_from(&config) {
+        let mut unknown = vec![];
+        spans.unknown_keys(&known, &mut vec![], &mut unknown);
+        problems.extend(unknown.into_iter().map(|key| Problem {
+            severity: Severity::Warning,
+            key,
+            message: "unknown key, ignored".to_string(),
+        }));
+    }
+    problems
+        .into_iter()
+        .map(|problem| {
+            let start = spans.locate(&problem.key).unwrap_or(0);
+            let (line, column) = line_column(text, start);
+            Diagnostic {
+                severity: problem.severity,
+                line,
+                column,
+                key: display_key(&problem.key),
+                message: problem.message,
+            }
+        })
+        .collect()
+}
+
+/// Fail with every error in `diagnostics`, one per line prefixed by `path`.
+pub fn bail_on_errors(path: &Path, diagnostics: &[Diagnostic]) -> Result<()> {
+    let errors: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .map(|d| format!("{}:{d}", path.display()))
+        .collect();
+    if !errors.is_empty() {
+        bail!("Invalid config:\n{}", errors.join("\n"));
+    }
+    Ok(())
+}
+
+/// Fail with every error in a parsed config, for when there is no text to point at.
+pub fn validate(config: &SerdeConfig) -> Result<()> {
+    let errors: Vec<_> = problems(config)
+        .into_iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .map(|problem| format!("{}: {}", display_key(&problem.key), problem.message))
+        .collect();
+    if !errors.is_empty() {
+        bail!("Invalid config: {}", errors.join("; "));
+    }
+    Ok(())
+}
+
+/// Fill in what a bare `host:port` lacks, e.g. `127.0.0.1:6800` becomes
+/// `http://127.0.0.1:6800/jsonrpc`.
+pub fn normalize_aria2_address(address: &str) -> Result<String> {
+    let address = address.trim();
+    let mut url = if address.contains("://") {
+        Url::parse(address)?
+    } else {
+        Url::parse(&format!("http://{address}"))?
+    };
+    if !ARIA2_SCHEMES.contains(&url.scheme()) {
+        bail!(
+            "unsupported scheme {}, use http, https, ws or wss",
+            url.scheme()
+        );
+    }
+    if url.host().is_none() {
+        bail!("missing host");
+    }
+    if matches!(url.path(), "" | "/") {
+        url.set_path("/jsonrpc");
+    }
+    Ok(url.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(&'static str),
+    /// A key only known from the text, e.g. a misspelled one
+    Name(String),
+    Index(usize),
+}
+
+struct Problem {
+    severity: Severity,
+    key: Vec<Segment>,
+    message: String,
+}
+
+/// Everything wrong with a parsed config, in the order of the keys.
+fn problems(config: &SerdeConfig) -> Vec<Problem> {
+    use Segment::{Index, Key};
+
+    let mut ret = vec![];
+    let mut error = |key: Vec<Segment>, message: String| {
+        ret.push(Problem {
+            severity: Severity::Error,
+            key,
+            message,
+        })
+    };
+    let mut warnings = vec![];
+    let mut warning = |key: Vec<Segment>, message: String| {
+        warnings.push(Problem {
+            severity: Severity::Warning,
+            key,
+            message,
+        })
+    };
+
+    match normalize_aria2_address(&config.aria2_address) {
+        Ok(normalized) if normalized != config.aria2_address => warning(
+            vec![Key("aria2_address")],
+            format!("normalized to {normalized}"),
+        ),
+        Ok(_) => {}
+        Err(e) => error(
+            vec![Key("aria2_address")],
+            format!("bad aria2 address: {e}"),
+        ),
+    }
+    if let Some(path) = &config.aria2_secret_file {
+        if !Path::new(path).is_file() {
+            // the secret in the environment wins, the file is never read then
+            if std::env::var_os(ARIA2_SECRET_ENV).is_some() {
+                warning(
+                    vec![Key("aria2_secret_file")],
+                    format!("{path} does not exist, using {ARIA2_SECRET_ENV}"),
+                );
+            } else {
+                error(
+                    vec![Key("aria2_secret_file")],
+                    format!("{path} does not exist"),
+                );
+            }
+        }
+    }
+    if let Some(dir) = config.aria2_options.as_ref().and_then(|o| o.get("dir")) {
+        // expanded for every feed without a dir of its own
+        if let Err(e) = template::check(dir) {
+            error(vec![Key("aria2_options"), Key("dir")], e.to_string());
+        } else if !Path::new(dir).is_absolute() {
+            warning(
+                vec![Key("aria2_options"), Key("dir")],
+                format!("{dir} is relative to where aria2 runs"),
+            );
+        }
+    }
+    if config.interval == Some(0) {
+        error(vec![Key("interval")], "must be positive".to_string());
+    }
+    if let Some(retry) = &config.retry {
+        let seconds = [
+            ("backoff", retry.backoff),
+            ("max_backoff", retry.max_backoff),
+            ("jitter", retry.jitter),
+        ];
+        for (key, value) in seconds {
+            if !value.is_finite() || value < 0.0 {
+                error(
+                    vec![Key("retry"), Key(key)],
+                    "must be a finite number, zero or more".to_string(),
+                );
+            }
+        }
+    }
+    for (i, tracker) in config.trackers.iter().flatten().enumerate() {
+        if let Err(e) = Url::parse(tracker) {
+            error(
+                vec![Key("trackers"), Index(i)],
+                format!("bad tracker url: {e}"),
+            );
+        }
+    }
+    let deprecated = [
+        ("url", config.url.is_some()),
+        ("file", config.file.is_some()),
+        ("filter", config.filter.is_some()),
+    ];
+    for (key, _) in deprecated.into_iter().filter(|(_, set)| *set) {
+        warning(
+            vec![Key(key)],
+            "deprecated, migrated into [[feed]] on load".to_string(),
+        );
+    }
+
+    let mut names = BTreeMap::new();
+    for (i, feed) in config.feed.iter().flatten().enumerate() {
+        let at = |key: &[Segment]| [&[Key("feed"), Index(i)], key].concat();
+        let first = *names.entry(feed.name.as_str()).or_insert(i);
+        if first != i {
+            error(
+                at(&[Key("name")]),
+                format!(
+                    "duplicate feed name \"{}\", first used by feed[{first}]",
+                    feed.name
+                ),
+            );
+        }
+        match &feed.source {
+            FeedSource::Url(url) => match Url::parse(url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                Ok(url) => error(
+                    at(&[Key("url")]),
+                    format!("unsupported scheme {}, use http or https", url.scheme()),
+                ),
+                Err(e) => error(at(&[Key("url")]), format!("bad feed url: {e}")),
+            },
+            FeedSource::Path(path) => {
+                if !Path::new(path).is_file() {
+                    warning(at(&[Key("path")]), format!("{path} does not exist yet"));
+                }
+            }
+        }
+        if let Some(dir) = &feed.dir {
+            if let Err(e) = template::check(dir) {
+                error(at(&[Key("dir")]), e.to_string());
+            } else if !Path::new(dir).is_absolute() {
+                warning(
+                    at(&[Key("dir")]),
+                    format!("{dir} is relative to where aria2 runs"),
+                );
+            }
+        }
+        if feed.interval == Some(0) {
+            error(at(&[Key("interval")]), "must be positive".to_string());
+        }
+        if let Some(schedule) = &feed.schedule {
+            if let Err(e) = Schedule::from_str(schedule) {
+                error(at(&[Key("schedule")]), format!("bad cron expression: {e}"));
+            }
+        }
+        if let Some(filter) = &feed.filter {
+            let lists = [("include", &filter.include), ("exclude", &filter.exclude)];
+            for (key, regexes) in lists {
+                for (j, re) in regexes.iter().flatten().enumerate() {
+                    if let Err(e) = Regex::new(re) {
+                        let e = e.to_string();
+                        // the last line of regex errors is the reason, the rest draws the pattern
+                        let reason = e.lines().last().unwrap_or_default().trim();
+                        let reason = reason.strip_prefix("error: ").unwrap_or(reason);
+                        error(
+                            at(&[Key("filter"), Key(key), Index(j)]),
+                            format!("bad regex: {reason}"),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    ret.extend(warnings);
+    ret
+}
+
+fn display_key(key: &[Segment]) -> String {
+    let mut ret = String::new();
+    for segment in key {
+        match segment {
+            Segment::Key(k) if ret.is_empty() => ret.push_str(k),
+            Segment::Name(k) if ret.is_empty() => ret.push_str(k),
+            Segment::Key(k) => {
+                ret.push('.');
+                ret.push_str(k);
+            }
+            Segment::Name(k) => {
+                ret.push('.');
+                ret.push_str(k);
+            }
+            Segment::Index(i) => ret.push_str(&format!("[{i}]")),
+        }
+    }
+    ret
+}
+
+/// 1-based line and column, in chars, of a byte offset.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Where every value of a TOML document is.
+enum Node {
+    Table(BTreeMap<String, Spanned<Node>>),
+    Array(Vec<Spanned<Node>>),
+    Leaf,
+}
+
+impl Node {
+    /// Start of the deepest value along `key`.
+    fn locate(&self, key: &[Segment]) -> Option<usize> {
+        let (first, rest) = key.split_first()?;
+        let child = match (self, first) {
+            (Self::Table(table), Segment::Key(k)) => table.get(*k)?,
+            (Self::Table(table), Segment::Name(k)) => table.get(k)?,
+            (Self::Array(array), Segment::Index(i)) => array.get(*i)?,
+            _ => return None,
+        };
+        child.get_ref().locate(rest).or(Some(child.span().start))
+    }
+
+    /// Keys of the text missing in `known`, the same document as parsed and serialized again.
+    fn unknown_keys(
+        &self,
+        known: &toml::Value,
+        key: &mut Vec<Segment>,
+        out: &mut Vec<Vec<Segment>>,
+    ) {
+        match (self, known) {
+            (Self::Table(table), toml::Value::Table(known)) => {
+                for (k, child) in table {
+                    key.push(Segment::Name(k.to_string()));
+                    match known.get(k) {
+                        Some(known) => child.get_ref().unknown_keys(known, key, out),
+                        None => out.push(key.clone()),
+                    }
+                    key.pop();
+                }
+            }
+            (Self::Array(array), toml::Value::Array(known)) => {
+                for (i, (child, known)) in array.iter().zip(known).enumerate() {
+                    key.push(Segment::Index(i));
+                    child.get_ref().unknown_keys(known, key, out);
+                    key.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a TOML value")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<Node, E> {
+        Ok(Node::Leaf)
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<Node, E> {
+        Ok(Node::Leaf)
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<Node, E> {
+        Ok(Node::Leaf)
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<Node, E> {
+        Ok(Node::Leaf)
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<Node, E> {
+        Ok(Node::Leaf)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+        let mut ret = vec![];
+        while let Some(value) = seq.next_element()? {
+            ret.push(value);
+        }
+        Ok(Node::Array(ret))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+        let mut ret = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry()? {
+            ret.insert(key, value);
+        }
+        Ok(Node::Table(ret))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_address() {
+        let normalize = |address| normalize_aria2_address(address).unwrap();
+        assert_eq!(normalize("127.0.0.1:6800"), "http://127.0.0.1:6800/jsonrpc");
+        assert_eq!(normalize("localhost:6800"), "http://localhost:6800/jsonrpc");
+        assert_eq!(normalize("ws://host:6800/"), "ws://host:6800/jsonrpc");
+        assert_eq!(normalize("https://host/rpc"), "https://host/rpc");
+        assert!(normalize_aria2_address("ftp://host:6800").is_err());
+    }
+
+    #[test]
+    fn diagnostics_point_at_values() {
+        let text = r#"aria2_address = "127.0.0.1:6800"
+
+[[feed]]
+name = "show"
+url = "https://example.com/rss"
+
+[[feed]]
+name = "show"
+url = "example.com/rss"
+
+[feed.filter]
+include = ["ok", "(unclosed"]
+"#;
+        let diagnostics: Vec<_> = check(text).iter().map(Diagnostic::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                "8:8: error: feed[1].name: duplicate feed name \"show\", first used by feed[0]",
+                "9:7: error: feed[1].url: bad feed url: relative URL without a base",
+                "12:18: error: feed[1].filter.include[1]: bad regex: unclosed group",
+                "1:17: warning: aria2_address: normalized to http://127.0.0.1:6800/jsonrpc",
+            ]
+        );
+
+        let text = "aria2_address = \"http://127.0.0.1:6800/jsonrpc\"\n\n\
+                    [retry]\nbackoff = -5.0\nmax_backoff = inf\n";
+        let diagnostics = check(text);
+        let diagnostics: Vec<_> = diagnostics.iter().map(Diagnostic::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                "4:11: error: retry.backoff: must be a finite number, zero or more",
+                "5:15: error: retry.max_backoff: must be a finite number, zero or more",
+            ]
+        );
+
+        let text = r#"aria2_address = "http://127.0.0.1:6800/jsonrpc"
+intervall = 0
+
+[aria2_options]
+dir = "/d/{nope}"
+
+[[feed]]
+name = "show"
+path = "/nonexistent/rss.xml"
+enable = false
+"#;
+        let diagnostics: Vec<_> = check(text).iter().map(Diagnostic::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                "5:7: error: aria2_options.dir: Unknown placeholder {nope} in path template",
+                "9:8: warning: feed[0].path: /nonexistent/rss.xml does not exist yet",
+                "10:10: warning: feed[0].enable: unknown key, ignored",
+                "2:13: warning: intervall: unknown key, ignored",
+            ]
+        );
+
+        let diagnostics = check("aria2_address = \n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 17));
+    }
+}
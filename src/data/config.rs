@@ -1,99 +1,805 @@
 use anyhow::{Context, Result};
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fs::File,
-    io::{self, Write},
+    io,
     path::Path,
-    time::SystemTime,
+    time::Duration,
 };
 
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
-use super::SyncFile;
+use super::{check, file::write_atomic, filter::SerdeFilter, store::Storage, SyncFile};
+use crate::{
+    client::retry::RetryPolicy,
+    feed::link::{LinkExtractor, LinkSource},
+    jsonrpc::Aria2Options,
+};
+
+/// Environment variable overriding `aria2_secret` and `aria2_secret_file`
+pub const ARIA2_SECRET_ENV: &str = "ARNI_ARIA2_SECRET";
+
+/// Poll interval in seconds when `interval` is not set
+const DEFAULT_INTERVAL: u64 = 3600;
 
 pub struct Config<'a> {
-    modified_time: SystemTime,
+    /// Contents as of the last sync
+    snapshot: String,
     path: &'a Path,
     inner: SerdeConfig,
+    secret: Option<String>,
+    /// Keys changed both on disk and in memory since the last sync
+    conflicts: Vec<String>,
 }
 
 impl<'a> Config<'a> {
-    pub fn new(path: &'a str) -> Result<Self> {
+    /// With `dry_run` nothing is written, a missing file is only assumed to be the default.
+    pub fn new(path: &'a str, dry_run: bool) -> Result<Self> {
         let path = Path::new(path);
-        let inner = if path.exists() {
+        let mut migrated = false;
+        let (inner, snapshot) = if path.exists() {
             let file = File::open(path).with_context(|| "Fail to open path.")?;
             let file =
                 io::read_to_string(file).with_context(|| "Fail to read on disk config file.")?;
-            toml::from_str(&file).with_context(|| "Fail to parse config file.")?
+            check::bail_on_errors(path, &check::check(&file))?;
+            let mut ret: SerdeConfig =
+                toml::from_str(&file).with_context(|| "Fail to parse config file.")?;
+            migrated = ret.migrate();
+            migrated |= ret.normalize();
+            (ret, file)
         } else {
-            let mut file = File::create(path).with_context(|| "Fail to create config file.")?;
             let ret = SerdeConfig::default();
-            file.write_all(
-                toml::to_string_pretty(&ret)
-                    .with_context(|| "Fail to write new config file back.")?
-                    .as_bytes(),
-            )?;
-            ret
+            let toml = toml::to_string_pretty(&ret)
+                .with_context(|| "Fail to write new config file back.")?;
+            if !dry_run {
+                write_atomic(path, toml.as_bytes())
+                    .with_context(|| "Fail to create config file.")?;
+            }
+            (ret, toml)
         };
-        let modified_time = path.metadata()?.modified()?;
 
-        Ok(Self {
-            modified_time,
+        let secret = inner.resolve_secret()?;
+
+        let mut ret = Self {
+            snapshot,
             path,
             inner,
-        })
+            secret,
+            conflicts: vec![],
+        };
+        if migrated && !dry_run {
+            info!("Writing migrated config back.");
+            ret.write_back()
+                .with_context(|| "Fail to write migrated config back.")?;
+        }
+
+        Ok(ret)
+    }
+
+    /// Keys edited on disk that conflicted with changes in memory since the last call, the
+    /// values on disk were kept.
+    pub fn take_conflicts(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.conflicts)
     }
 
     pub fn aria2_address(&self) -> &String {
         &self.inner.aria2_address
     }
 
-    pub fn url(&self) -> &Option<Vec<String>> {
-        &self.inner.url
+    /// Secret token of aria2 rpc, resolved from env, secret file and config in order.
+    pub fn aria2_secret(&self) -> Option<String> {
+        self.secret.clone()
     }
 
-    pub fn file(&self) -> &Option<Vec<String>> {
-        &self.inner.file
+    /// All feeds in config, including the disabled ones.
+    pub fn feeds(&self) -> &[SerdeFeed] {
+        self.inner.feed.as_deref().unwrap_or_default()
+    }
+
+    pub fn enabled_feeds(&self) -> impl Iterator<Item = &SerdeFeed> {
+        self.feeds().iter().filter(|feed| feed.enabled)
+    }
+
+    pub fn feed(&self, name: &str) -> Option<&SerdeFeed> {
+        self.feeds().iter().find(|feed| feed.name == name)
+    }
+
+    /// Default poll interval of feeds, and the longest time between two runs in watch mode.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.inner.interval.unwrap_or(DEFAULT_INTERVAL))
+    }
+
+    /// aria2 options for downloads of a feed.
+    ///
+    /// Global options are overridden by the feed's `options`, which are overridden by its `dir`.
+    pub fn aria2_options(&self, feed: Option<&str>) -> Aria2Options {
+        let mut ret = self.inner.aria2_options.clone().unwrap_or_default();
+        if let Some(feed) = feed.and_then(|name| self.feed(name)) {
+            if let Some(options) = &feed.options {
+                ret.extend(options.clone());
+            }
+            if let Some(dir) = &feed.dir {
+                ret.insert("dir".to_string(), dir.to_string());
+            }
+        }
+        ret
+    }
+
+    /// Retry policy of feed fetches and aria2 rpc calls.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.inner.retry.clone().unwrap_or_default()
+    }
+
+    /// What to do when aria2 fails on a download of a feed, the feed's `requeue` wins.
+    pub fn requeue_policy(&self, feed: Option<&str>) -> RequeuePolicy {
+        feed.and_then(|name| self.feed(name))
+            .and_then(|feed| feed.requeue.as_ref())
+            .or(self.inner.requeue.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Backend of history and the download list, only read on start.
+    pub fn storage(&self) -> Storage {
+        self.inner.storage.unwrap_or_default()
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.inner.retention.clone().unwrap_or_default()
+    }
+
+    /// What to do with downloads removed from aria2 before finishing.
+    pub fn on_removed(&self) -> OnRemoved {
+        self.inner.on_removed.unwrap_or_default()
+    }
+
+    /// How download links are found in items of a feed.
+    ///
+    /// The feed's `link_sources` and `trackers` override global ones.
+    pub fn link_extractor(&self, feed: Option<&str>) -> LinkExtractor {
+        let feed = feed.and_then(|name| self.feed(name));
+        let sources = feed
+            .and_then(|feed| feed.link_sources.as_ref())
+            .or(self.inner.link_sources.as_ref())
+            .map(|sources| sources.to_vec())
+            .unwrap_or(LinkSource::DEFAULT.to_vec());
+        let trackers = feed
+            .and_then(|feed| feed.trackers.as_ref())
+            .or(self.inner.trackers.as_ref())
+            .cloned()
+            .unwrap_or_default();
+        LinkExtractor::new(sources, trackers)
     }
 }
 
 impl SyncFile for Config<'_> {
-    fn modified_time(&self) -> &std::time::SystemTime {
-        &self.modified_time
+    fn path(&self) -> &Path {
+        self.path
     }
 
-    fn path(&self) -> &std::path::Path {
-        self.path
+    fn snapshot(&self) -> &str {
+        &self.snapshot
     }
 
-    fn merge(&mut self, on_disk: String) -> Result<()> {
-        let on_disk = toml::from_str::<SerdeConfig>(&on_disk)?;
-        self.inner = on_disk;
+    fn set_snapshot(&mut self, contents: String) {
+        self.snapshot = contents;
+    }
+
+    /// Keys are merged one by one, a key changed on both sides to different values is a conflict
+    /// and the value on disk wins.
+    fn merge(&mut self, base: &str, on_disk: &str) -> Result<()> {
+        check::bail_on_errors(self.path, &check::check(on_disk))?;
+        let mut on_disk = toml::from_str::<SerdeConfig>(on_disk)?;
+        on_disk.migrate();
+        on_disk.normalize();
+        // compare normalized values, not the text the user wrote
+        let mut conflicts = vec![];
+        let merged = match toml::from_str::<SerdeConfig>(base) {
+            Ok(mut base) => {
+                base.migrate();
+                base.normalize();
+                let merged = merge_values(
+                    Some(&toml::Value::try_from(&base)?),
+                    Some(toml::Value::try_from(&self.inner)?),
+                    Some(toml::Value::try_from(&on_disk)?),
+                    "",
+                    &mut conflicts,
+                );
+                match merged {
+                    Some(merged) => merged.try_into()?,
+                    None => on_disk,
+                }
+            }
+            Err(_) => on_disk,
+        };
+        // nothing is applied unless the result is good as a whole
+        merged.validate()?;
+        self.secret = merged.resolve_secret()?;
+        for key in &conflicts {
+            warn!("`{key}` changed both on disk and in Arni, keeping the value on disk.");
+        }
+        self.conflicts.extend(conflicts);
+        self.inner = merged;
 
         Ok(())
     }
 
-    fn write_back(&mut self) -> Result<()> {
-        let mut file = File::create(self.path).with_context(|| "Fail to write back.")?;
-        let toml = toml::to_string_pretty(&self.inner)?;
-        file.write_all(toml.as_bytes())?;
-        self.modified_time = self.path.metadata()?.modified()?;
-        Ok(())
+    fn serialize(&mut self) -> Result<String> {
+        Ok(toml::to_string_pretty(&self.inner)?)
+    }
+
+    /// The file is the user's, it is only rewritten, losing comments and formatting, if the
+    /// values differ, e.g. after a migration or an edit made in Arni.
+    fn differs_from(&self, on_disk: &str) -> bool {
+        toml::from_str::<SerdeConfig>(on_disk).map_or(true, |on_disk| on_disk != self.inner)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Three-way merge of a config value, tables are merged key by key.
+///
+/// `None` is a missing key. Conflicting keys are pushed to `conflicts` as dotted paths.
+fn merge_values(
+    base: Option<&toml::Value>,
+    ours: Option<toml::Value>,
+    theirs: Option<toml::Value>,
+    key: &str,
+    conflicts: &mut Vec<String>,
+) -> Option<toml::Value> {
+    if ours == theirs || base == ours.as_ref() {
+        return theirs;
+    }
+    if base == theirs.as_ref() {
+        return ours;
+    }
+    match (ours, theirs) {
+        (Some(toml::Value::Table(mut ours)), Some(toml::Value::Table(mut theirs))) => {
+            let base = base.and_then(|base| base.as_table());
+            let mut ret = toml::Table::new();
+            let keys: BTreeSet<_> = ours.keys().chain(theirs.keys()).cloned().collect();
+            for k in keys {
+                let path = if key.is_empty() {
+                    k.to_string()
+                } else {
+                    format!("{key}.{k}")
+                };
+                let base = base.and_then(|base| base.get(&k));
+                if let Some(value) =
+                    merge_values(base, ours.remove(&k), theirs.remove(&k), &path, conflicts)
+                {
+                    ret.insert(k, value);
+                }
+            }
+            Some(toml::Value::Table(ret))
+        }
+        (_, theirs) => {
+            conflicts.push(key.to_string());
+            theirs
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct SerdeConfig {
     pub aria2_address: String,
+    /// Value of aria2's `rpc-secret`
+    pub aria2_secret: Option<String>,
+    /// File containing aria2's `rpc-secret`, takes precedence over `aria2_secret`
+    pub aria2_secret_file: Option<String>,
+    /// aria2 options applied to every download, e.g. `dir` or `seed-ratio`
+    pub aria2_options: Option<Aria2Options>,
+    /// Default poll interval of feeds in seconds
+    pub interval: Option<u64>,
+    /// Where to look for download links in feed items, in order
+    pub link_sources: Option<Vec<LinkSource>>,
+    /// Trackers added to magnets built from an infohash
+    pub trackers: Option<Vec<String>>,
+    pub retry: Option<RetryPolicy>,
+    pub requeue: Option<RequeuePolicy>,
+    pub on_removed: Option<OnRemoved>,
+    pub retention: Option<Retention>,
+    /// Where history and the download list are kept, `toml` or `sqlite`
+    pub storage: Option<Storage>,
+    pub feed: Option<Vec<SerdeFeed>>,
+    /// Deprecated, migrated into `feed` on load
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub url: Option<Vec<String>>,
+    /// Deprecated, migrated into `feed` on load
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub file: Option<Vec<String>>,
+    /// Deprecated, filter rules keyed by feed url or file, migrated into `feed` on load
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<BTreeMap<String, SerdeFilter>>,
+}
+
+impl SerdeConfig {
+    fn resolve_secret(&self) -> Result<Option<String>> {
+        self.resolve_secret_with(std::env::var(ARIA2_SECRET_ENV).ok())
+    }
+
+    /// The secret from `env` if set, then `aria2_secret_file`, then `aria2_secret`.
+    fn resolve_secret_with(&self, env: Option<String>) -> Result<Option<String>> {
+        if let Some(secret) = env {
+            return Ok(Some(secret));
+        }
+        if let Some(path) = &self.aria2_secret_file {
+            let secret = std::fs::read_to_string(path)
+                .with_context(|| format!("Fail to read aria2 secret file {path}."))?;
+            return Ok(Some(secret.trim_end().to_string()));
+        }
+        Ok(self.aria2_secret.clone())
+    }
+
+    /// Move the flat `url` and `file` lists into `feed` entries.
+    ///
+    /// Returns true if anything has been migrated.
+    pub fn migrate(&mut self) -> bool {
+        if self.url.is_none() && self.file.is_none() && self.filter.is_none() {
+            return false;
+        }
+        info!("Migrating flat url and file lists into feeds.");
+
+        let mut filters = self.filter.take().unwrap_or_default();
+        let urls = self.url.take().unwrap_or_default();
+        let files = self.file.take().unwrap_or_default();
+        let sources = urls
+            .into_iter()
+            .map(FeedSource::Url)
+            .chain(files.into_iter().map(FeedSource::Path));
+
+        let feeds = self.feed.get_or_insert_with(Vec::new);
+        for source in sources {
+            let name = source.as_str().to_string();
+            if feeds.iter().any(|feed| feed.source == source) {
+                continue;
+            }
+            let mut feed = SerdeFeed::new(name, source);
+            feed.filter = filters.remove(feed.source.as_str());
+            feeds.push(feed);
+        }
+        // filters of feeds already in `feed`, or of none at all
+        for (source, filter) in filters {
+            match feeds.iter_mut().find(|feed| feed.source.as_str() == source) {
+                Some(feed) if feed.filter.is_none() => feed.filter = Some(filter),
+                Some(feed) => warn!(
+                    "Feed {} has a filter already, discarding the one under [filter.\"{source}\"].",
+                    feed.name
+                ),
+                None => warn!("No feed reads {source}, discarding [filter.\"{source}\"]."),
+            }
+        }
+
+        true
+    }
+
+    /// Check what parsing can't, so a bad edit is rejected as a whole instead of failing later.
+    pub fn validate(&self) -> Result<()> {
+        check::validate(self)
+    }
+
+    /// Fill in the scheme and path of a bare `host:port` aria2 address.
+    ///
+    /// Returns true if anything has been changed.
+    pub fn normalize(&mut self) -> bool {
+        match check::normalize_aria2_address(&self.aria2_address) {
+            Ok(address) if address != self.aria2_address => {
+                info!("Normalizing aria2 address to {address}.");
+                self.aria2_address = address;
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 impl Default for SerdeConfig {
     fn default() -> Self {
         Self {
-            aria2_address: "127.0.0.1:6800".to_string(),
+            aria2_address: "http://127.0.0.1:6800/jsonrpc".to_string(),
+            aria2_secret: None,
+            aria2_secret_file: None,
+            aria2_options: None,
+            interval: None,
+            link_sources: None,
+            trackers: None,
+            retry: None,
+            requeue: None,
+            on_removed: None,
+            retention: None,
+            storage: None,
+            feed: None,
             url: None,
             file: None,
+            filter: None,
+        }
+    }
+}
+
+/// What to do when aria2 fails on a download, the `[requeue]` table in config or a feed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequeuePolicy {
+    /// Times a failed download is sent again before giving up
+    pub max_retries: u32,
+    /// Send again with the next link found in the feed item, if there is one
+    pub alternate_link: bool,
+    /// aria2 options added to downloads sent again
+    pub options: Option<Aria2Options>,
+}
+
+impl Default for RequeuePolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            alternate_link: true,
+            options: None,
+        }
+    }
+}
+
+/// What to do with a download removed from aria2 before finishing, e.g. cancelled in a frontend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnRemoved {
+    /// Send it to aria2 again, up to `max_retries` of the requeue policy, then record it
+    Retry,
+    /// Keep it in history as removed without reporting it, so it is not sent again
+    Ignore,
+    /// Keep it in history as removed so it is never downloaded again, and report it
+    #[default]
+    Record,
+}
+
+/// When history records are pruned, the `[retention]` table in config. Nothing is pruned by
+/// default.
+///
+/// Episodes pruned by `max_age` or `max_entries` while still showing in a feed are downloaded
+/// again, `absent_days` is the safe choice for feeds keeping old items.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    /// Days after finishing
+    pub max_age: Option<u64>,
+    /// Keep only the latest records
+    pub max_entries: Option<usize>,
+    /// Days since the episode last showed in any feed, a feed not fetched in a run, e.g. not
+    /// modified, counts as still showing its episodes
+    pub absent_days: Option<u64>,
+}
+
+/// Where a feed is read from, written as either `url = "..."` or `path = "..."`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedSource {
+    Url(String),
+    Path(String),
+}
+
+impl FeedSource {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Url(s) | Self::Path(s) => s,
         }
     }
 }
+
+/// One `[[feed]]` entry in config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SerdeFeed {
+    pub name: String,
+    #[serde(flatten)]
+    pub source: FeedSource,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Download directory passed to aria2, may contain placeholders like `{title_sanitized}`
+    pub dir: Option<String>,
+    /// Poll interval in seconds, never shorter than the channel's `<ttl>`
+    pub interval: Option<u64>,
+    /// Cron expression with seconds, e.g. `0 0 20 * * Sat`, takes precedence over `interval`
+    pub schedule: Option<String>,
+    /// Extra aria2 options, keys are aria2 option names like `max-download-limit`
+    pub options: Option<Aria2Options>,
+    pub link_sources: Option<Vec<LinkSource>>,
+    pub trackers: Option<Vec<String>>,
+    pub requeue: Option<RequeuePolicy>,
+    pub filter: Option<SerdeFilter>,
+}
+
+impl SerdeFeed {
+    pub fn new(name: String, source: FeedSource) -> Self {
+        Self {
+            name,
+            source,
+            enabled: true,
+            dir: None,
+            interval: None,
+            schedule: None,
+            options: None,
+            link_sources: None,
+            trackers: None,
+            requeue: None,
+            filter: None,
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn parse_feed_table() {
+        let config: SerdeConfig = toml::from_str(
+            r#"
+            aria2_address = "http://127.0.0.1:6800/jsonrpc"
+
+            [[feed]]
+            name = "show"
+            url = "https://example.com/rss"
+            interval = 600
+
+            [feed.filter]
+            include = ["1080p"]
+
+            [[feed]]
+            name = "local"
+            path = "rss.xml"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        let feeds = config.feed.unwrap();
+        assert_eq!(
+            feeds[0].source,
+            FeedSource::Url("https://example.com/rss".to_string())
+        );
+        assert!(feeds[0].enabled);
+        assert_eq!(feeds[0].interval, Some(600));
+        assert!(feeds[0].filter.is_some());
+        assert_eq!(feeds[1].source, FeedSource::Path("rss.xml".to_string()));
+        assert!(!feeds[1].enabled);
+    }
+
+    #[test]
+    fn migrate_flat_lists() {
+        let mut config: SerdeConfig = toml::from_str(
+            r#"
+            aria2_address = "127.0.0.1:6800"
+            url = ["https://example.com/rss"]
+            file = ["rss.xml"]
+
+            [filter."rss.xml"]
+            keywords = ["CHS"]
+            "#,
+        )
+        .unwrap();
+        assert!(config.migrate());
+        assert!(!config.migrate());
+        let feeds = config.feed.as_ref().unwrap();
+        assert_eq!(feeds.len(), 2);
+        assert_eq!(feeds[0].name, "https://example.com/rss");
+        assert!(feeds[0].filter.is_none());
+        assert_eq!(feeds[1].source, FeedSource::Path("rss.xml".to_string()));
+        assert!(feeds[1].filter.is_some());
+
+        let toml = toml::to_string_pretty(&config).unwrap();
+        let round_trip: SerdeConfig = toml::from_str(&toml).unwrap();
+        assert_eq!(round_trip.feed, config.feed);
+        assert!(round_trip.url.is_none());
+
+        let mut config: SerdeConfig = toml::from_str(
+            r#"
+            aria2_address = "127.0.0.1:6800"
+
+            [filter."https://example.com/rss"]
+            keywords = ["CHS"]
+
+            [filter."https://example.com/gone"]
+            keywords = ["CHT"]
+
+            [[feed]]
+            name = "show"
+            url = "https://example.com/rss"
+            "#,
+        )
+        .unwrap();
+        assert!(config.migrate());
+        let feeds = config.feed.as_ref().unwrap();
+        assert_eq!(feeds.len(), 1);
+        let keywords = feeds[0].filter.as_ref().unwrap().keywords.as_ref();
+        assert_eq!(keywords.unwrap(), &["CHS"]);
+    }
+
+    #[test]
+    fn feed_options_override_global() {
+        let inner: SerdeConfig = toml::from_str(
+            r#"
+            aria2_address = "http://127.0.0.1:6800/jsonrpc"
+
+            [aria2_options]
+            dir = "/downloads"
+            seed-ratio = "1.0"
+
+            [[feed]]
+            name = "show"
+            url = "https://example.com/rss"
+            dir = "/downloads/show"
+
+            [feed.options]
+            seed-ratio = "0"
+            "#,
+        )
+        .unwrap();
+        let config = Config {
+            snapshot: String::new(),
+            path: Path::new("config.toml"),
+            inner,
+            secret: None,
+            conflicts: vec![],
+        };
+
+        let options = config.aria2_options(Some("show"));
+        assert_eq!(options["dir"], "/downloads/show");
+        assert_eq!(options["seed-ratio"], "0");
+        let options = config.aria2_options(None);
+        assert_eq!(options["dir"], "/downloads");
+        assert_eq!(options["seed-ratio"], "1.0");
+    }
+
+    #[test]
+    fn secret_file_over_inline_secret() {
+        let dir = TempDir::new("secret");
+        let path = dir.join("secret");
+        std::fs::write(&path, "from-file\n").unwrap();
+        let mut config = SerdeConfig {
+            aria2_secret: Some("inline".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.resolve_secret_with(None).unwrap(),
+            Some("inline".to_string())
+        );
+        config.aria2_secret_file = Some(path.to_string_lossy().to_string());
+        assert_eq!(
+            config.resolve_secret_with(None).unwrap(),
+            Some("from-file".to_string())
+        );
+        assert_eq!(
+            config
+                .resolve_secret_with(Some("from-env".to_string()))
+                .unwrap(),
+            Some("from-env".to_string())
+        );
+    }
+
+    #[test]
+    fn feed_link_sources_override_global() {
+        let config: SerdeConfig = toml::from_str(
+            r#"
+            aria2_address = "127.0.0.1:6800"
+            link_sources = ["enclosure", "info_hash"]
+            trackers = ["udp://tracker.example.com:1337/announce"]
+
+            [[feed]]
+            name = "show"
+            url = "https://example.com/rss"
+            link_sources = ["description"]
+            "#,
+        )
+        .unwrap();
+        let config = Config {
+            snapshot: String::new(),
+            path: Path::new("config.toml"),
+            inner: config,
+            secret: None,
+            conflicts: vec![],
+        };
+        let expected = LinkExtractor::new(
+            vec![LinkSource::Description],
+            vec!["udp://tracker.example.com:1337/announce".to_string()],
+        );
+        assert_eq!(config.link_extractor(Some("show")), expected);
+        let expected = LinkExtractor::new(
+            vec![LinkSource::Enclosure, LinkSource::InfoHash],
+            vec!["udp://tracker.example.com:1337/announce".to_string()],
+        );
+        assert_eq!(config.link_extractor(None), expected);
+    }
+
+    #[test]
+    fn merge_config_edits() {
+        let value = |toml: &str| Some(toml::Value::Table(toml::from_str(toml).unwrap()));
+        let base = value("interval = 600\n[retry]\nmax_attempts = 3\nbackoff = 1.0");
+        let ours = value("interval = 600\n[retry]\nmax_attempts = 5\nbackoff = 1.0");
+        let theirs = value("interval = 60\n[retry]\nmax_attempts = 3\nbackoff = 2.0");
+        let mut conflicts = vec![];
+        let merged = merge_values(base.as_ref(), ours, theirs, "", &mut conflicts);
+        assert_eq!(
+            merged,
+            value("interval = 60\n[retry]\nmax_attempts = 5\nbackoff = 2.0")
+        );
+        assert!(conflicts.is_empty());
+
+        let ours = value("interval = 300\n[retry]\nmax_attempts = 3\nbackoff = 1.0");
+        let theirs = value("interval = 60\n[retry]\nmax_attempts = 3");
+        let merged = merge_values(base.as_ref(), ours, theirs.clone(), "", &mut conflicts);
+        assert_eq!(merged, theirs);
+        assert_eq!(conflicts, vec!["interval".to_string()]);
+    }
+
+    #[test]
+    fn reject_invalid_config() {
+        let config: SerdeConfig = toml::from_str(
+            r#"
+            aria2_address = "127.0.0.1:6800"
+
+            [[feed]]
+            name = "show"
+            url = "https://example.com/rss"
+
+            [[feed]]
+            name = "show"
+            path = "show.xml"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        let config: SerdeConfig = toml::from_str(
+            r#"
+            aria2_address = "127.0.0.1:6800"
+
+            [[feed]]
+            name = "show"
+            url = "https://example.com/rss"
+            schedule = "every saturday"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn keep_user_formatting() {
+        let dir = TempDir::new("config");
+        let path = dir.file("config.toml");
+        let text =
+            "# my aria2\naria2_address = \"http://127.0.0.1:6800/jsonrpc\"\ninterval = 600\n";
+        std::fs::write(&path, text).unwrap();
+        let mut config = Config::new(&path, false).unwrap();
+        config.sync().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+
+        // an edit is picked up and the file left as written
+        let text = text.replace("600", "60  # faster");
+        std::fs::write(&path, &text).unwrap();
+        config.sync().unwrap();
+        assert_eq!(config.interval(), Duration::from_secs(60));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+        assert!(!dir.join("config.toml.1").exists());
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let dir = TempDir::new("config-dry");
+        let path = dir.file("config.toml");
+        Config::new(&path, true).unwrap();
+        assert!(!dir.join("config.toml").exists());
+
+        // normalized in memory only
+        let text = "# my aria2\naria2_address = \"127.0.0.1:6800\"\ninterval = 600\n";
+        std::fs::write(&path, text).unwrap();
+        let mut config = Config::new(&path, true).unwrap();
+        let text = text.replace("600", "60");
+        std::fs::write(&path, &text).unwrap();
+        config.reload().unwrap();
+        assert_eq!(config.interval(), Duration::from_secs(60));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+        assert!(!dir.join("config.toml.1").exists());
+    }
+}
@@ -1,23 +1,56 @@
-use crate::error::Error;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Eq)]
+use crate::{
+    error::Error,
+    feed::{link::LinkExtractor, FeedItem},
+    jsonrpc::types::Aria2Status,
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DownloadStatus {
     /// Waiting for sending to aria2
     Waiting,
     /// Sent to aria2
     Sent,
     /// Finished downloading
-    Done,
+    #[serde(alias = "done")]
+    Completed,
+    /// Removed from aria2 before finishing, e.g. cancelled by the user
+    Removed,
     /// Something went wrong on the aria2 side
     Error,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Episode {
     pub guid: String,
     pub title: Option<String>,
     pub torrent_link: String,
+    /// Name of the feed this episode comes from
+    pub feed: Option<String>,
+    /// Publish date of the feed item, in RFC 2822
+    pub pub_date: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    /// Download directory expanded from the feed's path template
+    pub dir: Option<String>,
+    /// Other links found in the feed item, tried in turn when aria2 fails on the current one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternate_links: Vec<String>,
     pub gid: Option<String>,
     pub download_status: DownloadStatus,
+    /// When aria2 accepted the latest attempt
+    pub sent_at: Option<DateTime<Utc>>,
+    /// Total length in bytes, once aria2 knows it
+    pub size: Option<u64>,
+    /// Times the episode has been sent again after aria2 failed on it
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub retries: u32,
+    /// aria2's `errorCode` of the last failure
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
 }
 
 impl Episode {
@@ -26,11 +59,26 @@ impl Episode {
             guid,
             title,
             torrent_link,
+            feed: None,
+            pub_date: None,
+            categories: vec![],
+            dir: None,
+            alternate_links: vec![],
             gid: None,
             download_status: DownloadStatus::Waiting,
+            sent_at: None,
+            size: None,
+            retries: 0,
+            error_code: None,
+            error_message: None,
         }
     }
 
+    /// Title of the episode for humans, or its guid if there is no title.
+    pub fn name(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.guid)
+    }
+
     pub fn is_waiting(&self) -> bool {
         self.download_status == DownloadStatus::Waiting
     }
@@ -39,22 +87,85 @@ impl Episode {
         self.download_status == DownloadStatus::Sent
     }
 
-    pub fn is_done(&self) -> bool {
-        self.download_status == DownloadStatus::Done
+    pub fn is_completed(&self) -> bool {
+        self.download_status == DownloadStatus::Completed
+    }
+
+    pub fn is_removed(&self) -> bool {
+        self.download_status == DownloadStatus::Removed
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.download_status == DownloadStatus::Error
+    }
+
+    /// Convert a feed item, whose download link is found by `extractor`.
+    pub fn from_item(value: FeedItem, extractor: &LinkExtractor) -> Result<Self, Error> {
+        let mut links = extractor.extract_all(&value).into_iter();
+        let Some(torrent_link) = links.next() else {
+            return Err(Error::BadTorrentLink);
+        };
+        let guid = value.guid.unwrap_or_else(|| torrent_link.to_string());
+        let mut ret = Self::new(guid, value.title, torrent_link);
+        ret.alternate_links = links.collect();
+        ret.pub_date = value.pub_date;
+        ret.categories = value.categories;
+        Ok(ret)
     }
 
     pub fn set_download_status(&mut self, status: &str) -> Result<(), Error> {
         self.download_status = match status {
             "active" | "waiting" | "paused" => DownloadStatus::Sent,
             "error" => DownloadStatus::Error,
-            "complete" | "removed" => DownloadStatus::Done,
+            "complete" => DownloadStatus::Completed,
+            "removed" => DownloadStatus::Removed,
             _ => return Err(Error::ImpossibleEpisodeState),
         };
 
         Ok(())
     }
 
+    /// Update from aria2's status of our gid.
+    ///
+    /// A finished torrent file or metadata download is followed by the actual content download,
+    /// in which case we switch to tracking that gid instead.
+    pub fn update_status(&mut self, status: &Aria2Status) -> Result<(), Error> {
+        if status.total_length > 0 {
+            self.size = Some(status.total_length);
+        }
+        if status.status == "complete" {
+            if let Some(gid) = status.followed_by.first() {
+                self.gid = Some(gid.to_string());
+                self.download_status = DownloadStatus::Sent;
+                return Ok(());
+            }
+        }
+        if status.status == "error" {
+            self.error_code = status.error_code.clone();
+            self.error_message = status.error_message.clone();
+        }
+        self.set_download_status(&status.status)
+    }
+
+    /// Put a failed episode back to waiting, with the next alternate link if `alternate_link`.
+    pub fn requeue(&mut self, alternate_link: bool) {
+        if alternate_link && !self.alternate_links.is_empty() {
+            let link = self.alternate_links.remove(0);
+            let failed = std::mem::replace(&mut self.torrent_link, link);
+            self.alternate_links.push(failed);
+        }
+        self.retries += 1;
+        self.set_waiting();
+    }
+
+    /// Forget the gid and wait for sending again.
+    pub fn set_waiting(&mut self) {
+        self.gid = None;
+        self.download_status = DownloadStatus::Waiting;
+    }
+
     pub fn set_sent(&mut self) {
+        self.sent_at = Some(Utc::now());
         self.download_status = DownloadStatus::Sent;
     }
 
@@ -66,26 +177,88 @@ impl Episode {
     }
 }
 
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
 impl PartialEq for Episode {
     fn eq(&self, other: &Self) -> bool {
         self.guid == other.guid
     }
 }
 
-impl TryFrom<rss::Item> for Episode {
+impl TryFrom<FeedItem> for Episode {
     type Error = Error;
 
-    fn try_from(value: rss::Item) -> std::result::Result<Self, Self::Error> {
-        let torrent_link = match value.enclosure() {
-            Some(enclosure) => enclosure.url().to_string(),
-            None => return Err(Error::BadTorrentLink),
+    fn try_from(value: FeedItem) -> std::result::Result<Self, Self::Error> {
+        Self::from_item(value, &LinkExtractor::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follow_torrent_download() {
+        let mut episode = Episode::new(
+            "guid".to_string(),
+            None,
+            "https://example.com/a.torrent".to_string(),
+        );
+        episode.gid = Some("torrent".to_string());
+        episode.set_sent();
+
+        let status = Aria2Status {
+            status: "complete".to_string(),
+            followed_by: vec!["content".to_string()],
+            ..Default::default()
         };
-        let guid = match value.guid() {
-            Some(guid) => guid.value().to_string(),
-            // if there is None, the function will return before this unwrap()
-            None => value.enclosure().unwrap().url().to_string(),
+        episode.update_status(&status).unwrap();
+        assert!(episode.is_sent());
+        assert_eq!(episode.gid.as_deref(), Some("content"));
+
+        let status = Aria2Status {
+            status: "complete".to_string(),
+            ..Default::default()
+        };
+        episode.update_status(&status).unwrap();
+        assert!(episode.is_completed());
+
+        let status = Aria2Status {
+            status: "removed".to_string(),
+            ..Default::default()
+        };
+        episode.update_status(&status).unwrap();
+        assert!(episode.is_removed());
+    }
+
+    #[test]
+    fn requeue_with_alternate_link() {
+        let mut episode = Episode::new("guid".to_string(), None, "a".to_string());
+        episode.alternate_links = vec!["b".to_string()];
+        episode.gid = Some("gid".to_string());
+        episode.set_sent();
+
+        let status = Aria2Status {
+            status: "error".to_string(),
+            error_code: Some("3".to_string()),
+            error_message: Some("Resource not found".to_string()),
+            ..Default::default()
         };
-        let title = value.title().map(|title| title.to_string());
-        Ok(Self::new(guid, title, torrent_link))
+        episode.update_status(&status).unwrap();
+        assert!(episode.is_error());
+        assert_eq!(episode.error_code.as_deref(), Some("3"));
+
+        episode.requeue(true);
+        assert!(episode.is_waiting());
+        assert_eq!(episode.gid, None);
+        assert_eq!(episode.retries, 1);
+        assert_eq!(episode.torrent_link, "b");
+        episode.requeue(true);
+        assert_eq!(episode.torrent_link, "a");
+        episode.requeue(false);
+        assert_eq!(episode.torrent_link, "a");
+        assert_eq!(episode.retries, 3);
     }
 }
@@ -0,0 +1,196 @@
+//! Crash-safe writes of the TOML files and the lock on the working directory.
+//!
+//! A file is never truncated in place: the new content goes to a temporary file beside it, which
+//! is flushed to disk and renamed over the old one. The previous content is kept in rotating
+//! backups, `history.toml.1` being the latest.
+
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{ErrorKind, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use log::debug;
+
+use crate::error::Error;
+
+/// Backups kept of each file
+pub const BACKUPS: usize = 3;
+
+/// Name of the lock file in the working directory
+pub const LOCK_FILE: &str = ".arni.lock";
+
+/// Atomically replace `path` with `contents`.
+///
+/// Nothing is written if the file already has the same contents, so backups are only rotated on
+/// actual changes. A symlink is followed and its target replaced, and the permissions of the old
+/// file are kept, config may hold the aria2 secret behind a `chmod 600`.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut permissions = None;
+    let path = match fs::canonicalize(path) {
+        Ok(path) => {
+            if fs::read(&path).is_ok_and(|on_disk| on_disk == contents) {
+                debug!("{} is up to date.", path.display());
+                return Ok(());
+            }
+            rotate_backups(&path)?;
+            permissions = Some(fs::metadata(&path)?.permissions());
+            path
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => path.to_path_buf(),
+        Err(e) => return Err(e).with_context(|| format!("Fail to resolve {}.", path.display())),
+    };
+
+    // unique, so two writers never share a temporary file
+    let tmp = sibling(&path, &format!(".{}.tmp", std::process::id()));
+    let write = || -> Result<()> {
+        let mut file =
+            File::create(&tmp).with_context(|| format!("Fail to create {}.", tmp.display()))?;
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        file.write_all(contents)?;
+        file.sync_all()
+            .with_context(|| format!("Fail to flush {}.", tmp.display()))?;
+        fs::rename(&tmp, &path).with_context(|| format!("Fail to replace {}.", path.display()))
+    };
+    if let Err(e) = write() {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    sync_dir(&path);
+    Ok(())
+}
+
+/// Shift `path.1` .. `path.N-1` up by one and copy the current file to `path.1`.
+fn rotate_backups(path: &Path) -> Result<()> {
+    if BACKUPS == 0 {
+        return Ok(());
+    }
+    for n in (1..BACKUPS).rev() {
+        let from = sibling(path, &format!(".{n}"));
+        if from.exists() {
+            fs::rename(&from, sibling(path, &format!(".{}", n + 1)))?;
+        }
+    }
+    // copy rather than rename, the live file must exist at any moment
+    fs::copy(path, sibling(path, ".1"))
+        .with_context(|| format!("Fail to back up {}.", path.display()))?;
+    Ok(())
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Make the rename itself durable, best effort as not every platform can open a directory.
+fn sync_dir(path: &Path) {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+/// Exclusive advisory lock on a working directory, held until dropped.
+///
+/// The operating system releases the lock when the process dies, so a crash never leaves a stale
+/// lock behind.
+#[derive(Debug)]
+pub struct WorkDirLock {
+    _file: File,
+}
+
+impl WorkDirLock {
+    /// Lock `dir`, failing with [`Error::WorkingDirLocked`] if another process holds it.
+    pub fn acquire(dir: &Path) -> Result<Self> {
+        let path = dir.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Fail to open {}.", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                return Err(Error::WorkingDirLocked(
+                    dir.display().to_string(),
+                    pid.trim().parse().ok(),
+                )
+                .into());
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Fail to lock {}.", path.display()))
+            }
+        }
+        // tell whoever is refused who holds the lock
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn write_with_backups() {
+        let dir = TempDir::new("file");
+        let path = dir.join("history.toml");
+        for n in 0..5 {
+            write_atomic(&path, format!("{n}").as_bytes()).unwrap();
+        }
+        // unchanged content rotates nothing
+        write_atomic(&path, b"4").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "4");
+        assert_eq!(fs::read_to_string(sibling(&path, ".1")).unwrap(), "3");
+        assert_eq!(fs::read_to_string(sibling(&path, ".3")).unwrap(), "1");
+        assert!(!sibling(&path, ".4").exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keep_permissions_and_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = TempDir::new("link");
+        let target = dir.join("real.toml");
+        let link = dir.join("config.toml");
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+        symlink(&target, &link).unwrap();
+
+        write_atomic(&link, b"new").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn refuse_second_lock() {
+        let dir = TempDir::new("lock");
+        let lock = WorkDirLock::acquire(dir.path()).unwrap();
+        let e = WorkDirLock::acquire(dir.path()).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::WorkingDirLocked(_, Some(pid))) if *pid == std::process::id()
+        ));
+        drop(lock);
+        WorkDirLock::acquire(dir.path()).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt::Formatter;
+
+use anyhow::{Context, Result};
+use log::debug;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use super::episode::Episode;
+
+/// Filter rules of one feed as written in config.
+///
+/// Every rule must pass for an episode to be downloaded.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SerdeFilter {
+    /// Regexes the title must match
+    pub include: Option<Vec<String>>,
+    /// Regexes the title must not match
+    pub exclude: Option<Vec<String>>,
+    /// Keywords the title must contain
+    pub keywords: Option<Vec<String>>,
+    /// Keywords the title must not contain
+    pub exclude_keywords: Option<Vec<String>>,
+    /// Ignore case in both regexes and keywords, default to false
+    pub case_insensitive: Option<bool>,
+}
+
+enum Rule {
+    Include(Regex),
+    Exclude(Regex),
+    Keyword(String),
+    ExcludeKeyword(String),
+}
+
+impl Rule {
+    fn is_match(&self, title: &str) -> bool {
+        match self {
+            Self::Include(re) => re.is_match(title),
+            Self::Exclude(re) => !re.is_match(title),
+            Self::Keyword(k) => title.contains(k.as_str()),
+            Self::ExcludeKeyword(k) => !title.contains(k.as_str()),
+        }
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Include(re) => write!(f, "include /{re}/"),
+            Self::Exclude(re) => write!(f, "exclude /{re}/"),
+            Self::Keyword(k) => write!(f, "keyword \"{k}\""),
+            Self::ExcludeKeyword(k) => write!(f, "exclude keyword \"{k}\""),
+        }
+    }
+}
+
+/// Compiled form of [`SerdeFilter`].
+pub struct Filter {
+    rules: Vec<Rule>,
+    case_insensitive: bool,
+}
+
+impl Filter {
+    pub fn new(filter: &SerdeFilter) -> Result<Self> {
+        let case_insensitive = filter.case_insensitive.unwrap_or(false);
+        let regex = |re: &String| {
+            RegexBuilder::new(re)
+                .case_insensitive(case_insensitive)
+                .build()
+                .with_context(|| format!("Bad filter regex: {re}"))
+        };
+        let keyword = |k: &String| {
+            if case_insensitive {
+                k.to_lowercase()
+            } else {
+                k.to_string()
+            }
+        };
+
+        let mut rules = vec![];
+        for re in filter.include.iter().flatten() {
+            rules.push(Rule::Include(regex(re)?));
+        }
+        for re in filter.exclude.iter().flatten() {
+            rules.push(Rule::Exclude(regex(re)?));
+        }
+        for k in filter.keywords.iter().flatten() {
+            rules.push(Rule::Keyword(keyword(k)));
+        }
+        for k in filter.exclude_keywords.iter().flatten() {
+            rules.push(Rule::ExcludeKeyword(keyword(k)));
+        }
+
+        Ok(Self {
+            rules,
+            case_insensitive,
+        })
+    }
+
+    /// Check an episode against every rule. Episodes without a title are matched as empty string.
+    pub fn is_match(&self, episode: &Episode) -> bool {
+        let title = episode.title.as_deref().unwrap_or_default();
+        let name = episode.title.as_deref().unwrap_or(&episode.guid);
+        let lowercase;
+        let title = if self.case_insensitive {
+            lowercase = title.to_lowercase();
+            lowercase.as_str()
+        } else {
+            title
+        };
+
+        for rule in self.rules.iter() {
+            if rule.is_match(title) {
+                debug!("Filter rule {rule} matched: {name}");
+            } else {
+                debug!("Filter rule {rule} rejected: {name}");
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(title: &str) -> Episode {
+        Episode::new(
+            title.to_string(),
+            Some(title.to_string()),
+            "https://example.com/a.torrent".to_string(),
+        )
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = Filter::new(&SerdeFilter::default()).unwrap();
+        assert!(filter.is_match(&episode("[Sub] Show - 01 [720p]")));
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let filter = Filter::new(&SerdeFilter {
+            include: Some(vec![r"\[1080p\]".to_string()]),
+            exclude: Some(vec!["(?i)raw".to_string()]),
+            keywords: Some(vec!["CHS".to_string()]),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(filter.is_match(&episode("[Sub] Show - 01 [CHS][1080p]")));
+        assert!(!filter.is_match(&episode("[Sub] Show - 01 [CHS][720p]")));
+        assert!(!filter.is_match(&episode("[Sub] Show - 01 [CHS][1080p][RAW]")));
+        assert!(!filter.is_match(&episode("[Sub] Show - 01 [CHT][1080p]")));
+    }
+
+    #[test]
+    fn case_insensitive_keywords() {
+        let filter = Filter::new(&SerdeFilter {
+            exclude_keywords: Some(vec!["HEVC".to_string()]),
+            case_insensitive: Some(true),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!filter.is_match(&episode("[Sub] Show - 01 [hevc]")));
+        assert!(filter.is_match(&episode("[Sub] Show - 01 [avc]")));
+    }
+
+    #[test]
+    fn bad_regex() {
+        let filter = Filter::new(&SerdeFilter {
+            include: Some(vec!["[".to_string()]),
+            ..Default::default()
+        });
+        assert!(filter.is_err());
+    }
+}
@@ -1,83 +1,551 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs::File,
-    io::{self, Write},
+    io,
     path::Path,
-    time::SystemTime,
 };
 
 use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::info;
 use serde::{Deserialize, Serialize};
 
-use super::SyncFile;
+use super::{config::Retention, episode::Episode, file::write_atomic, SyncFile};
 
 pub struct History<'a> {
-    modified_time: SystemTime,
+    /// Contents as of the last sync
+    snapshot: String,
     path: &'a Path,
     inner: SerdeHistory,
-    delta: Vec<String>,
+    /// Records not yet written back, kept apart so they survive merging an edited file
+    delta: Vec<HistoryRecord>,
+    /// Guids of every record in `inner` and `delta`
+    index: HashSet<String>,
+    /// When recorded episodes were last seen in a feed, not yet written back
+    seen: HashMap<String, DateTime<Utc>>,
+    /// Feeds not fetched in a run, their recorded episodes count as still showing
+    seen_feeds: HashMap<String, DateTime<Utc>>,
+    /// Applied on every write back
+    retention: Retention,
 }
 
 impl<'a> History<'a> {
-    pub fn new(path: &'a str) -> Result<Self> {
+    /// With `dry_run` nothing is written, a missing file is only assumed to be the default.
+    pub fn new(path: &'a str, dry_run: bool) -> Result<Self> {
         let path = Path::new(path);
-        let inner = if path.exists() {
+        let mut migrated = false;
+        let (inner, snapshot) = if path.exists() {
             let file = File::open(path).with_context(|| "Fail to open path.")?;
             let file =
                 io::read_to_string(file).with_context(|| "Fail to read on disk history file.")?;
-            toml::from_str(&file).with_context(|| "Fail to parse history file.")?
+            let mut ret: SerdeHistory =
+                toml::from_str(&file).with_context(|| "Fail to parse history file.")?;
+            migrated = ret.migrate();
+            (ret, file)
         } else {
-            let mut file = File::create(path).with_context(|| "Fail to create history file.")?;
             let ret = SerdeHistory::default();
-            file.write_all(toml::to_string_pretty(&ret)?.as_bytes())?;
-            ret
+            let file = toml::to_string_pretty(&ret)?;
+            if !dry_run {
+                write_atomic(path, file.as_bytes())
+                    .with_context(|| "Fail to create history file.")?;
+            }
+            (ret, file)
         };
-        let modified_time = path.metadata()?.modified()?;
 
-        Ok(Self {
-            modified_time,
+        let mut ret = Self {
+            snapshot,
             path,
             inner,
             delta: vec![],
-        })
+            index: HashSet::new(),
+            seen: HashMap::new(),
+            seen_feeds: HashMap::new(),
+            retention: Retention::default(),
+        };
+        ret.reindex();
+        if migrated && !dry_run {
+            info!("Writing migrated history back.");
+            ret.write_back()
+                .with_context(|| "Fail to write migrated history back.")?;
+        }
+
+        Ok(ret)
     }
 
+    /// Whether the episode has been downloaded, removed or given up on.
     pub fn query(&self, guid: &str) -> bool {
-        let guid = guid.to_string();
-        self.inner.downloaded.contains(&guid) || self.delta.contains(&guid)
+        self.index.contains(guid)
+    }
+
+    /// Latest record of an episode.
+    pub fn get(&self, guid: &str) -> Option<&HistoryRecord> {
+        if !self.query(guid) {
+            return None;
+        }
+        self.records().filter(|record| record.guid == guid).last()
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &HistoryRecord> {
+        self.inner.record.iter().chain(self.delta.iter())
+    }
+
+    pub fn push(&mut self, record: HistoryRecord) {
+        self.index.insert(record.guid.to_string());
+        self.delta.push(record)
+    }
+
+    /// Note that a recorded episode still shows in a feed, see [`Retention::absent_days`].
+    pub fn seen(&mut self, guid: &str, now: DateTime<Utc>) {
+        if self.query(guid) {
+            self.seen.insert(guid.to_string(), now);
+        }
+    }
+
+    /// Note that a feed was not fetched, e.g. not modified or not due, so its recorded episodes
+    /// are taken as still showing in it rather than absent.
+    pub fn seen_feed(&mut self, feed: &str, now: DateTime<Utc>) {
+        self.seen_feeds.insert(feed.to_string(), now);
+    }
+
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+    }
+
+    /// Remove records expired under the retention policy, returns them.
+    ///
+    /// With `dry_run` nothing is removed, expired records are only returned.
+    pub fn prune(&mut self, now: DateTime<Utc>, dry_run: bool) -> Vec<HistoryRecord> {
+        self.flush();
+        let expired = expired(&self.inner.record, &self.retention, now);
+        let mut ret = vec![];
+        let mut i = 0;
+        self.inner.record.retain(|record| {
+            let keep = !expired.contains(&i);
+            i += 1;
+            if !keep {
+                ret.push(record.clone());
+            }
+            keep || dry_run
+        });
+        if !dry_run {
+            self.reindex();
+        }
+        ret
+    }
+
+    /// Move pending changes into `inner`.
+    fn flush(&mut self) {
+        self.inner.record.append(&mut self.delta);
+        for record in self.inner.record.iter_mut() {
+            let feed_seen = record
+                .feed
+                .as_ref()
+                .and_then(|feed| self.seen_feeds.get(feed));
+            if let Some(seen) = self.seen.get(&record.guid).max(feed_seen) {
+                record.last_seen = Some(*seen);
+            }
+        }
+        self.seen.clear();
+        self.seen_feeds.clear();
     }
 
-    pub fn push(&mut self, guid: &str) {
-        self.delta.push(guid.to_string())
+    fn reindex(&mut self) {
+        self.index = self
+            .records()
+            .map(|record| record.guid.to_string())
+            .collect();
     }
 }
 
 impl SyncFile for History<'_> {
-    fn modified_time(&self) -> &std::time::SystemTime {
-        &self.modified_time
+    fn path(&self) -> &Path {
+        self.path
     }
 
-    fn path(&self) -> &std::path::Path {
-        self.path
+    fn snapshot(&self) -> &str {
+        &self.snapshot
     }
 
-    fn merge(&mut self, on_disk: String) -> Result<()> {
-        let on_disk = toml::from_str::<SerdeHistory>(&on_disk)?;
+    fn set_snapshot(&mut self, contents: String) {
+        self.snapshot = contents;
+    }
+
+    /// Records added on either side are kept and records removed on either side stay removed,
+    /// pending records are merged on write back.
+    fn merge(&mut self, base: &str, on_disk: &str) -> Result<()> {
+        let mut on_disk = toml::from_str::<SerdeHistory>(on_disk)?;
+        on_disk.migrate();
+        // a broken base only means records removed on disk come back
+        let mut base = toml::from_str::<SerdeHistory>(base).unwrap_or_default();
+        base.migrate();
+        let ours = std::mem::take(&mut self.inner.record);
+        on_disk.record = merge_records(&base.record, ours, on_disk.record);
         self.inner = on_disk;
-        self.inner.downloaded.append(&mut self.delta);
-        self.delta.clear();
+        self.reindex();
 
         Ok(())
     }
 
-    fn write_back(&mut self) -> Result<()> {
-        let mut file = File::create(self.path)?;
-        file.write_all(toml::to_string_pretty(&self.inner)?.as_bytes())?;
-        self.modified_time = self.path.metadata()?.modified()?;
-        Ok(())
+    fn serialize(&mut self) -> Result<String> {
+        let pruned = self.prune(Utc::now(), false);
+        if !pruned.is_empty() {
+            info!("Pruned {} history records.", pruned.len());
+        }
+        Ok(toml::to_string_pretty(&self.inner)?)
+    }
+}
+
+/// How an episode left the download list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Completed,
+    /// Removed from aria2 before finishing
+    Removed,
+    /// Given up on after aria2 failed on it too many times
+    Failed,
+}
+
+/// One `[[record]]` in history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    pub guid: String,
+    pub title: Option<String>,
+    /// Name of the feed the episode comes from
+    pub feed: Option<String>,
+    /// Link of the last attempt
+    pub link: Option<String>,
+    pub gid: Option<String>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Last time the episode showed in a feed after it had been recorded
+    pub last_seen: Option<DateTime<Utc>>,
+    pub status: Outcome,
+    /// Total length in bytes reported by aria2
+    pub size: Option<u64>,
+    /// aria2's `errorCode`, see the EXIT STATUS section of aria2's manual
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+}
+
+impl HistoryRecord {
+    /// Record of an episode leaving the download list just now.
+    pub fn new(episode: &Episode, status: Outcome) -> Self {
+        Self {
+            guid: episode.guid.to_string(),
+            title: episode.title.clone(),
+            feed: episode.feed.clone(),
+            link: Some(episode.torrent_link.to_string()),
+            gid: episode.gid.clone(),
+            sent_at: episode.sent_at,
+            finished_at: Some(Utc::now()),
+            last_seen: None,
+            status,
+            size: episode.size,
+            error_code: episode.error_code.clone(),
+            error_message: episode.error_message.clone(),
+        }
+    }
+
+    /// Record migrated from a bare guid, nothing else is known about it.
+    fn migrated(guid: String, status: Outcome) -> Self {
+        Self {
+            guid,
+            title: None,
+            feed: None,
+            link: None,
+            gid: None,
+            sent_at: None,
+            finished_at: None,
+            last_seen: None,
+            status,
+            size: None,
+            error_code: None,
+            error_message: None,
+        }
+    }
+
+    /// Identity of a record when merging, the rest may be updated in place.
+    fn key(&self) -> RecordKey {
+        (
+            self.guid.to_string(),
+            self.status,
+            self.sent_at,
+            self.finished_at,
+        )
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct SerdeHistory {
-    downloaded: Vec<String>,
+    #[serde(default)]
+    record: Vec<HistoryRecord>,
+    /// Deprecated, migrated into `record` on load
+    #[serde(skip_serializing_if = "Option::is_none")]
+    downloaded: Option<Vec<String>>,
+    /// Deprecated, migrated into `record` on load
+    #[serde(skip_serializing_if = "Option::is_none")]
+    removed: Option<Vec<String>>,
+    /// Deprecated, migrated into `record` on load
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failed: Option<Vec<FailedDownload>>,
+}
+
+impl SerdeHistory {
+    /// Move the flat guid lists into records.
+    ///
+    /// Returns true if anything has been migrated.
+    pub fn migrate(&mut self) -> bool {
+        if self.downloaded.is_none() && self.removed.is_none() && self.failed.is_none() {
+            return false;
+        }
+        info!("Migrating history into records.");
+
+        let downloaded = self.downloaded.take().unwrap_or_default();
+        let removed = self.removed.take().unwrap_or_default();
+        let failed = self.failed.take().unwrap_or_default();
+        let records = downloaded
+            .into_iter()
+            .map(|guid| HistoryRecord::migrated(guid, Outcome::Completed))
+            .chain(
+                removed
+                    .into_iter()
+                    .map(|guid| HistoryRecord::migrated(guid, Outcome::Removed)),
+            )
+            .chain(failed.into_iter().map(|failed| HistoryRecord {
+                title: failed.title,
+                link: Some(failed.link),
+                error_code: failed.error_code,
+                error_message: failed.error_message,
+                ..HistoryRecord::migrated(failed.guid, Outcome::Failed)
+            }));
+        self.record.extend(records);
+
+        true
+    }
+}
+
+type RecordKey = (
+    String,
+    Outcome,
+    Option<DateTime<Utc>>,
+    Option<DateTime<Utc>>,
+);
+
+/// Three-way set union of records, with `base` the records both sides started from.
+///
+/// A record on both sides keeps the later `last_seen`. Records on disk keep their order, followed
+/// by the ones only added in memory.
+fn merge_records(
+    base: &[HistoryRecord],
+    ours: Vec<HistoryRecord>,
+    theirs: Vec<HistoryRecord>,
+) -> Vec<HistoryRecord> {
+    let base: HashSet<_> = base.iter().map(HistoryRecord::key).collect();
+    let mut ours: HashMap<_, _> = ours
+        .into_iter()
+        .enumerate()
+        .map(|(i, record)| (record.key(), (i, record)))
+        .collect();
+    let mut ret = vec![];
+    for mut record in theirs {
+        let key = record.key();
+        match ours.remove(&key) {
+            Some((_, our)) => {
+                record.last_seen = record.last_seen.max(our.last_seen);
+                ret.push(record);
+            }
+            // removed in memory, e.g. pruned
+            None if base.contains(&key) => {}
+            None => ret.push(record),
+        }
+    }
+    let mut added: Vec<_> = ours
+        .into_values()
+        .filter(|(_, record)| !base.contains(&record.key()))
+        .collect();
+    added.sort_by_key(|(i, _)| *i);
+    ret.extend(added.into_iter().map(|(_, record)| record));
+    ret
+}
+
+/// Indices of `records` expired under `retention`, records are in the order they were added.
+fn expired(records: &[HistoryRecord], retention: &Retention, now: DateTime<Utc>) -> HashSet<usize> {
+    let days = |days: u64| Duration::days(days.try_into().unwrap_or(i64::MAX));
+    let older_than = |time: Option<DateTime<Utc>>, days: Duration| {
+        // records without a timestamp can't expire, there is no telling how old they are
+        time.is_some_and(|time| now.signed_duration_since(time) > days)
+    };
+    let mut ret = HashSet::new();
+    for (i, record) in records.iter().enumerate() {
+        if let Some(max_age) = retention.max_age {
+            if older_than(record.finished_at, days(max_age)) {
+                ret.insert(i);
+            }
+        }
+        if let Some(absent_days) = retention.absent_days {
+            if older_than(record.last_seen.or(record.finished_at), days(absent_days)) {
+                ret.insert(i);
+            }
+        }
+    }
+    if let Some(max_entries) = retention.max_entries {
+        // records are appended in order, the oldest come first
+        let excess = records.len().saturating_sub(max_entries);
+        ret.extend(0..excess);
+    }
+    ret
+}
+
+/// Deprecated `[[failed]]` entry, migrated into a record on load.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FailedDownload {
+    guid: String,
+    title: Option<String>,
+    link: String,
+    error_code: Option<String>,
+    error_message: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn migrate_guid_lists() {
+        let mut history: SerdeHistory = toml::from_str(
+            r#"
+            downloaded = ["a", "b"]
+            removed = ["c"]
+
+            [[failed]]
+            guid = "d"
+            link = "d.torrent"
+            error_code = "3"
+            "#,
+        )
+        .unwrap();
+        assert!(history.migrate());
+        assert!(!history.migrate());
+        let status: Vec<_> = history.record.iter().map(|r| r.status).collect();
+        assert_eq!(
+            status,
+            vec![
+                Outcome::Completed,
+                Outcome::Completed,
+                Outcome::Removed,
+                Outcome::Failed
+            ]
+        );
+        assert_eq!(history.record[3].error_code.as_deref(), Some("3"));
+
+        let toml = toml::to_string_pretty(&history).unwrap();
+        assert!(!toml.contains("downloaded"));
+    }
+
+    #[test]
+    fn expire_records() {
+        let now = Utc::now();
+        let record = |guid: &str, finished_days: i64, seen_days: Option<i64>| HistoryRecord {
+            finished_at: Some(now - Duration::days(finished_days)),
+            last_seen: seen_days.map(|days| now - Duration::days(days)),
+            ..HistoryRecord::migrated(guid.to_string(), Outcome::Completed)
+        };
+        let records = vec![
+            record("a", 100, Some(1)),
+            record("b", 40, None),
+            record("c", 10, Some(10)),
+            record("d", 1, None),
+            HistoryRecord::migrated("e".to_string(), Outcome::Completed),
+        ];
+        let expired = |retention| {
+            let mut ret: Vec<_> = expired(&records, &retention, now).into_iter().collect();
+            ret.sort();
+            ret
+        };
+
+        let max_age = Retention {
+            max_age: Some(90),
+            ..Default::default()
+        };
+        assert_eq!(expired(max_age), vec![0]);
+        let absent_days = Retention {
+            absent_days: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(expired(absent_days), vec![1]);
+        let max_entries = Retention {
+            max_entries: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(expired(max_entries), vec![0, 1]);
+        assert!(expired(Retention::default()).is_empty());
+    }
+
+    #[test]
+    fn feeds_not_fetched_are_not_absent() {
+        let dir = TempDir::new("seen");
+        let path = dir.file("history.toml");
+        let mut history = History::new(&path, false).unwrap();
+        let now = Utc::now();
+        for feed in ["fetched", "not modified"] {
+            history.push(HistoryRecord {
+                feed: Some(feed.to_string()),
+                finished_at: Some(now - Duration::days(40)),
+                ..HistoryRecord::migrated(feed.to_string(), Outcome::Completed)
+            });
+        }
+        history.set_retention(Retention {
+            absent_days: Some(30),
+            ..Default::default()
+        });
+        history.seen_feed("not modified", now);
+        let pruned = history.prune(now, false);
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].guid, "fetched");
+        assert_eq!(history.get("not modified").unwrap().last_seen, Some(now));
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let dir = TempDir::new("history");
+        let path = dir.file("history.toml");
+
+        let mut history = History::new(&path, false).unwrap();
+        let mut episode = Episode::new("a".to_string(), None, "a.torrent".to_string());
+        episode.feed = Some("show".to_string());
+        episode.size = Some(1024);
+        history.push(HistoryRecord::new(&episode, Outcome::Completed));
+        assert!(history.query("a") && !history.query("b"));
+        history.sync().unwrap();
+
+        let history = History::new(&path, false).unwrap();
+        let record = history.get("a").unwrap();
+        assert_eq!(record.feed.as_deref(), Some("show"));
+        assert_eq!(record.size, Some(1024));
+        assert!(record.finished_at.is_some());
+    }
+
+    #[test]
+    fn merge_records_as_sets() {
+        let now = Utc::now();
+        let record = |guid: &str| HistoryRecord::migrated(guid.to_string(), Outcome::Completed);
+        let base = vec![record("a"), record("b")];
+        // pruned b and recorded c in memory
+        let ours = vec![record("a"), record("c")];
+        // seen a, removed nothing and recorded d on disk
+        let theirs = vec![
+            HistoryRecord {
+                last_seen: Some(now),
+                ..record("a")
+            },
+            record("b"),
+            record("d"),
+        ];
+        let merged = merge_records(&base, ours, theirs);
+        let guids: Vec<_> = merged.iter().map(|r| r.guid.as_str()).collect();
+        assert_eq!(guids, vec!["a", "d", "c"]);
+        assert_eq!(merged[0].last_seen, Some(now));
+    }
 }
@@ -1,44 +1,92 @@
 use anyhow::Result;
 
+pub mod cache;
+pub mod check;
 pub mod config;
 pub mod episode;
+pub mod file;
+pub mod filter;
 pub mod history;
+pub mod sqlite;
+pub mod state;
+pub mod store;
+pub mod template;
 
 use log::{debug, info, warn};
 
+use file::write_atomic;
+
+/// A file shared between Arni and the user, kept in sync by a three-way merge.
+///
+/// Changes on disk are detected by comparing contents against a snapshot of the last sync rather
+/// than modified times, which are too coarse on some filesystems to tell apart two writes.
 pub trait SyncFile {
-    fn modified_time(&self) -> &std::time::SystemTime;
     fn path(&self) -> &std::path::Path;
-    fn merge(&mut self, on_disk: String) -> Result<()>;
-    fn write_back(&mut self) -> Result<()>;
+    /// Contents of the file as of the last sync, the common base of a merge.
+    fn snapshot(&self) -> &str;
+    fn set_snapshot(&mut self, contents: String);
+    /// Merge changes made on disk since `base` into memory.
+    fn merge(&mut self, base: &str, on_disk: &str) -> Result<()>;
+    /// Contents of the file as in memory.
+    fn serialize(&mut self) -> Result<String>;
+
+    /// Whether memory holds anything `on_disk` lacks, so writing back is worth it.
+    ///
+    /// Files whose formatting is Arni's own are always written back, it is a no-op when nothing
+    /// changed.
+    fn differs_from(&self, _on_disk: &str) -> bool {
+        true
+    }
+
+    /// Whether the file differs from the snapshot, or can't be read.
+    fn changed_on_disk(&self) -> bool {
+        std::fs::read_to_string(self.path()).map_or(true, |on_disk| on_disk != self.snapshot())
+    }
+
+    fn write_back(&mut self) -> Result<()> {
+        let contents = self.serialize()?;
+        write_atomic(self.path(), contents.as_bytes())?;
+        self.set_snapshot(contents);
+        Ok(())
+    }
 
     fn sync(&mut self) -> Result<()> {
-        info!("Syncing...");
-        info!("Compare modified time");
-        let disk_access_time = self.path().metadata()?.modified()?;
-        match self.modified_time().duration_since(disk_access_time) {
-            // Ok means file has not been modified since our last accessed
-            Ok(_) => {
-                info!("Arni believes we don't need to write back.");
-                self.write_back()?
-            }
-            // Err means file has benn modified since out last accesed
-            Err(_) => {
-                info!("Arni believes we need to sync with on disk file");
-                debug!("Opening on disk file");
-                let on_disk = std::fs::File::open(self.path()).map_err(|e| {
-                    warn!("Fail to open on disk file: {}", e);
-                    e
-                })?;
-                let on_disk = std::io::read_to_string(on_disk).map_err(|e| {
-                    warn!("Fail to read on disk file: {}", e);
-                    e
-                })?;
-                self.merge(on_disk)?;
-                self.write_back()?;
-            }
+        info!("Syncing {}...", self.path().display());
+        let on_disk = std::fs::read_to_string(self.path())
+            .inspect_err(|e| warn!("Fail to read on disk file: {e}"))?;
+        if on_disk == self.snapshot() {
+            debug!("Not changed on disk since the last sync.");
+        } else {
+            info!("Changed on disk since the last sync, merging.");
+            let base = self.snapshot().to_string();
+            self.merge(&base, &on_disk)?;
+        }
+        if !self.differs_from(&on_disk) {
+            debug!("Nothing to write back.");
+            self.set_snapshot(on_disk);
+            return Ok(());
         }
+        self.write_back()
+    }
 
+    /// Merge changes made on disk since the last sync without writing anything, for dry runs.
+    ///
+    /// A missing file has nothing to pick up.
+    fn reload(&mut self) -> Result<()> {
+        let on_disk = match std::fs::read_to_string(self.path()) {
+            Ok(on_disk) => on_disk,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                warn!("Fail to read on disk file: {e}");
+                return Err(e.into());
+            }
+        };
+        if on_disk != self.snapshot() {
+            info!("Changed on disk since the last sync, merging.");
+            let base = self.snapshot().to_string();
+            self.merge(&base, &on_disk)?;
+            self.set_snapshot(on_disk);
+        }
         Ok(())
     }
 }
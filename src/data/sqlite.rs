@@ -0,0 +1,540 @@
+//! SQLite backend of history and the download list, see [`super::store`].
+//!
+//! Both live in one database file, every sync is a single transaction so a crash never leaves a
+//! half written store behind.
+
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use rusqlite::{
+    params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, OpenFlags, OptionalExtension, Row, ToSql,
+};
+
+use super::{
+    config::Retention,
+    episode::Episode,
+    history::{History, HistoryRecord, Outcome},
+    state::State,
+    store::{HistoryStore, StateStore},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guid TEXT NOT NULL,
+    title TEXT,
+    feed TEXT,
+    link TEXT,
+    gid TEXT,
+    sent_at TEXT,
+    finished_at TEXT,
+    last_seen TEXT,
+    status TEXT NOT NULL,
+    size INTEGER,
+    error_code TEXT,
+    error_message TEXT
+);
+CREATE INDEX IF NOT EXISTS history_guid ON history (guid);
+CREATE INDEX IF NOT EXISTS history_feed ON history (feed);
+CREATE INDEX IF NOT EXISTS history_finished_at ON history (finished_at);
+CREATE INDEX IF NOT EXISTS history_absent ON history (coalesce(last_seen, finished_at));
+CREATE TABLE IF NOT EXISTS download_list (
+    position INTEGER PRIMARY KEY,
+    episode TEXT NOT NULL
+);
+";
+
+const RECORD_COLUMNS: &str = "guid, title, feed, link, gid, sent_at, finished_at, last_seen, \
+                              status, size, error_code, error_message";
+
+/// Open the database, with `dry_run` read only and without creating it or its schema.
+fn open(path: &str, dry_run: bool) -> Result<Connection> {
+    let conn = if dry_run {
+        Connection::open_with_flags(Path::new(path), OpenFlags::SQLITE_OPEN_READ_ONLY)
+    } else {
+        Connection::open(Path::new(path))
+    }
+    .with_context(|| format!("Fail to open database {path}."))?;
+    // another process may hold the write lock for a moment
+    conn.busy_timeout(Duration::from_secs(5))?;
+    if !dry_run {
+        conn.execute_batch(SCHEMA)
+            .with_context(|| "Fail to create database schema.")?;
+    }
+    Ok(conn)
+}
+
+fn has_no_data(conn: &Connection) -> Result<bool> {
+    conn.query_row(
+        "SELECT NOT EXISTS (SELECT 1 FROM history) AND NOT EXISTS (SELECT 1 FROM download_list)",
+        [],
+        |row| row.get(0),
+    )
+    .with_context(|| "Fail to read database.")
+}
+
+/// Whether the database is missing or has neither history nor a download list, i.e.
+/// [`import_toml`] would still import into it. Nothing is written.
+pub fn is_empty(path: &str) -> Result<bool> {
+    if !Path::new(path).exists() {
+        return Ok(true);
+    }
+    has_no_data(&open(path, true)?)
+}
+
+/// Import `history.toml` and `state.toml` into a database that has neither history nor a
+/// download list yet, so switching to `storage = "sqlite"` doesn't send everything again.
+///
+/// Both are imported in one transaction, files that don't exist are skipped.
+pub fn import_toml(path: &str, history: &str, state: &str) -> Result<()> {
+    let mut conn = open(path, false)?;
+    if !has_no_data(&conn)? {
+        return Ok(());
+    }
+    let history = match Path::new(history).exists() {
+        true => {
+            Some(History::new(history, false).with_context(|| "Fail to read history to import.")?)
+        }
+        false => None,
+    };
+    let state = match Path::new(state).exists() {
+        true => Some(State::new(state, false).with_context(|| "Fail to read state to import.")?),
+        false => None,
+    };
+    let records: Vec<_> = history
+        .iter()
+        .flat_map(|history| history.records())
+        .collect();
+    let episodes: Vec<_> = state
+        .iter()
+        .flat_map(|state| state.download_list())
+        .collect();
+    if records.is_empty() && episodes.is_empty() {
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    insert_records(&tx, records.iter().copied())?;
+    insert_episodes(&tx, episodes.iter().copied())?;
+    tx.commit()
+        .with_context(|| format!("Fail to import TOML files into {path}."))?;
+    info!(
+        "Imported {} history records and {} episodes into {path}.",
+        records.len(),
+        episodes.len()
+    );
+    Ok(())
+}
+
+fn insert_records<'a>(
+    conn: &Connection,
+    records: impl IntoIterator<Item = &'a HistoryRecord>,
+) -> Result<()> {
+    let mut insert = conn.prepare(&format!(
+        "INSERT INTO history ({RECORD_COLUMNS}) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)"
+    ))?;
+    for r in records {
+        insert.execute(params![
+            r.guid,
+            r.title,
+            r.feed,
+            r.link,
+            r.gid,
+            r.sent_at,
+            r.finished_at,
+            r.last_seen,
+            r.status,
+            r.size.map(|size| size as i64),
+            r.error_code,
+            r.error_message,
+        ])?;
+    }
+    Ok(())
+}
+
+fn insert_episodes<'a>(
+    conn: &Connection,
+    episodes: impl IntoIterator<Item = &'a Episode>,
+) -> Result<()> {
+    let mut insert =
+        conn.prepare("INSERT INTO download_list (position, episode) VALUES (?1, ?2)")?;
+    for (i, episode) in episodes.into_iter().enumerate() {
+        insert.execute(params![i as i64, serde_json::to_string(episode)?])?;
+    }
+    Ok(())
+}
+
+pub struct SqliteHistory {
+    conn: Connection,
+    /// Records not yet committed
+    delta: Vec<HistoryRecord>,
+    seen: HashMap<String, DateTime<Utc>>,
+    seen_feeds: HashMap<String, DateTime<Utc>>,
+    retention: Retention,
+}
+
+impl SqliteHistory {
+    /// With `dry_run` the database is opened read only, it must exist already.
+    pub fn new(path: &str, dry_run: bool) -> Result<Self> {
+        Ok(Self {
+            conn: open(path, dry_run)?,
+            delta: vec![],
+            seen: HashMap::new(),
+            seen_feeds: HashMap::new(),
+            retention: Retention::default(),
+        })
+    }
+
+    /// Commit pending records in one transaction, pruning expired ones unless `dry_run`.
+    fn commit(&mut self, now: DateTime<Utc>, dry_run: bool) -> Result<Vec<HistoryRecord>> {
+        let tx = self.conn.transaction()?;
+        insert_records(&tx, &self.delta)?;
+        {
+            let mut update = tx.prepare("UPDATE history SET last_seen = ?1 WHERE guid = ?2")?;
+            for (guid, seen) in &self.seen {
+                update.execute(params![seen, guid])?;
+            }
+            let mut update = tx.prepare("UPDATE history SET last_seen = ?1 WHERE feed = ?2")?;
+            for (feed, seen) in &self.seen_feeds {
+                update.execute(params![seen, feed])?;
+            }
+        }
+
+        // the same rules as `history::expired`, timestamps are stored in UTC so they compare as
+        // text
+        let max_age = self.retention.max_age.and_then(|days| cutoff(now, days));
+        let absent = self
+            .retention
+            .absent_days
+            .and_then(|days| cutoff(now, days));
+        let max_entries = self.retention.max_entries.map(|n| n as i64);
+        let mut clauses = vec![];
+        let mut values: Vec<(&str, &dyn ToSql)> = vec![];
+        if let Some(max_age) = &max_age {
+            clauses.push("finished_at < :max_age");
+            values.push((":max_age", max_age));
+        }
+        if let Some(absent) = &absent {
+            clauses.push("coalesce(last_seen, finished_at) < :absent");
+            values.push((":absent", absent));
+        }
+        if let Some(max_entries) = &max_entries {
+            clauses.push(
+                "id <= (SELECT id FROM history ORDER BY id DESC LIMIT 1 OFFSET :max_entries)",
+            );
+            values.push((":max_entries", max_entries));
+        }
+
+        let mut pruned = vec![];
+        if !clauses.is_empty() {
+            let filter = clauses.join(" OR ");
+            let mut select = tx.prepare(&format!(
+                "SELECT {RECORD_COLUMNS} FROM history WHERE {filter} ORDER BY id"
+            ))?;
+            pruned = select
+                .query_map(values.as_slice(), record)?
+                .collect::<rusqlite::Result<_>>()?;
+            drop(select);
+            if !dry_run {
+                tx.execute(
+                    &format!("DELETE FROM history WHERE {filter}"),
+                    values.as_slice(),
+                )?;
+            }
+        }
+        tx.commit()?;
+
+        self.delta.clear();
+        self.seen.clear();
+        self.seen_feeds.clear();
+        Ok(pruned)
+    }
+
+    fn latest(&self, guid: &str) -> Result<Option<HistoryRecord>> {
+        let ret = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {RECORD_COLUMNS} FROM history WHERE guid = ?1 ORDER BY id DESC LIMIT 1"
+                ),
+                [guid],
+                record,
+            )
+            .optional()?;
+        Ok(ret)
+    }
+}
+
+impl HistoryStore for SqliteHistory {
+    /// Fails closed, an episode that can't be looked up counts as recorded and waits for the
+    /// next run rather than being sent twice.
+    fn query(&self, guid: &str) -> bool {
+        if self.delta.iter().any(|record| record.guid == guid) {
+            return true;
+        }
+        self.conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM history WHERE guid = ?1)",
+                [guid],
+                |row| row.get(0),
+            )
+            .unwrap_or_else(|e| {
+                warn!("Fail to query history for {guid}, skipping it this run: {e}");
+                true
+            })
+    }
+
+    fn get(&self, guid: &str) -> Option<HistoryRecord> {
+        if let Some(record) = self.delta.iter().rev().find(|record| record.guid == guid) {
+            return Some(record.clone());
+        }
+        self.latest(guid)
+            .inspect_err(|e| warn!("Fail to query history for {guid}: {e}"))
+            .ok()
+            .flatten()
+    }
+
+    fn push(&mut self, record: HistoryRecord) {
+        self.delta.push(record)
+    }
+
+    fn seen(&mut self, guid: &str, now: DateTime<Utc>) {
+        if self.query(guid) {
+            self.seen.insert(guid.to_string(), now);
+        }
+    }
+
+    fn seen_feed(&mut self, feed: &str, now: DateTime<Utc>) {
+        self.seen_feeds.insert(feed.to_string(), now);
+    }
+
+    fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>, dry_run: bool) -> Result<Vec<HistoryRecord>> {
+        self.commit(now, dry_run)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let pruned = self.commit(Utc::now(), false)?;
+        if !pruned.is_empty() {
+            info!("Pruned {} history records.", pruned.len());
+        }
+        Ok(())
+    }
+}
+
+/// `days` before `now`, `None` if that is out of range and nothing can be that old.
+fn cutoff(now: DateTime<Utc>, days: u64) -> Option<DateTime<Utc>> {
+    let secs = days.checked_mul(24 * 60 * 60)?;
+    let days = chrono::Duration::from_std(Duration::from_secs(secs)).ok()?;
+    now.checked_sub_signed(days)
+}
+
+fn record(row: &Row) -> rusqlite::Result<HistoryRecord> {
+    Ok(HistoryRecord {
+        guid: row.get(0)?,
+        title: row.get(1)?,
+        feed: row.get(2)?,
+        link: row.get(3)?,
+        gid: row.get(4)?,
+        sent_at: row.get(5)?,
+        finished_at: row.get(6)?,
+        last_seen: row.get(7)?,
+        status: row.get(8)?,
+        size: row.get::<_, Option<i64>>(9)?.map(|size| size as u64),
+        error_code: row.get(10)?,
+        error_message: row.get(11)?,
+    })
+}
+
+impl ToSql for Outcome {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let ret = match self {
+            Outcome::Completed => "completed",
+            Outcome::Removed => "removed",
+            Outcome::Failed => "failed",
+        };
+        Ok(ret.into())
+    }
+}
+
+impl FromSql for Outcome {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "completed" => Ok(Outcome::Completed),
+            "removed" => Ok(Outcome::Removed),
+            "failed" => Ok(Outcome::Failed),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// The download list, stored as one json encoded episode per row.
+pub struct SqliteState {
+    conn: Connection,
+    inner: Vec<Episode>,
+}
+
+impl SqliteState {
+    /// With `dry_run` the database is opened read only, it must exist already.
+    pub fn new(path: &str, dry_run: bool) -> Result<Self> {
+        let conn = open(path, dry_run)?;
+        let inner = {
+            let mut select = conn.prepare("SELECT episode FROM download_list ORDER BY position")?;
+            let rows = select.query_map([], |row| row.get::<_, String>(0))?;
+            let mut ret = vec![];
+            for row in rows {
+                ret.push(serde_json::from_str(&row?).with_context(|| "Bad episode in database.")?);
+            }
+            ret
+        };
+        Ok(Self { conn, inner })
+    }
+}
+
+impl StateStore for SqliteState {
+    fn download_list(&self) -> &Vec<Episode> {
+        &self.inner
+    }
+
+    fn download_list_mut(&mut self) -> &mut Vec<Episode> {
+        &mut self.inner
+    }
+
+    /// The list is owned by Arni, it replaces whatever is stored.
+    fn sync(&mut self) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM download_list", [])?;
+        insert_episodes(&tx, &self.inner)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data::SyncFile, testing::TempDir};
+
+    #[test]
+    fn history_in_database() {
+        let mut history = SqliteHistory::new(":memory:", false).unwrap();
+        let mut episode = Episode::new("a".to_string(), None, "a.torrent".to_string());
+        episode.size = Some(1024);
+        history.push(HistoryRecord::new(&episode, Outcome::Completed));
+        assert!(history.query("a"));
+        history.sync().unwrap();
+        assert!(history.query("a") && !history.query("b"));
+        let record = history.get("a").unwrap();
+        assert_eq!(record.size, Some(1024));
+        assert_eq!(record.status, Outcome::Completed);
+
+        let episode = Episode::new("b".to_string(), None, "b.torrent".to_string());
+        history.push(HistoryRecord::new(&episode, Outcome::Removed));
+        history.set_retention(Retention {
+            max_entries: Some(1),
+            ..Default::default()
+        });
+        let pruned = history.prune(Utc::now(), true).unwrap();
+        assert_eq!(pruned[0].guid, "a");
+        assert!(history.query("a"));
+        history.sync().unwrap();
+        assert!(!history.query("a") && history.query("b"));
+    }
+
+    #[test]
+    fn expire_in_database() {
+        let now = Utc::now();
+        let days = |days| Some(now - chrono::Duration::days(days));
+        let mut history = SqliteHistory::new(":memory:", false).unwrap();
+        for (guid, finished_at, last_seen) in [
+            ("old", days(100), None),
+            ("absent", days(40), None),
+            ("seen", days(100), days(1)),
+            ("recent", days(10), None),
+            ("unknown", None, None),
+        ] {
+            let episode = Episode::new(guid.to_string(), None, format!("{guid}.torrent"));
+            history.push(HistoryRecord {
+                finished_at,
+                last_seen,
+                ..HistoryRecord::new(&episode, Outcome::Completed)
+            });
+        }
+        history.sync().unwrap();
+
+        history.set_retention(Retention {
+            absent_days: Some(30),
+            ..Default::default()
+        });
+        let pruned = history.prune(now, true).unwrap();
+        let guids: Vec<_> = pruned.iter().map(|r| r.guid.as_str()).collect();
+        assert_eq!(guids, ["old", "absent"]);
+
+        history.set_retention(Retention {
+            max_age: Some(90),
+            max_entries: Some(2),
+            ..Default::default()
+        });
+        let pruned = history.prune(now, true).unwrap();
+        let guids: Vec<_> = pruned.iter().map(|r| r.guid.as_str()).collect();
+        assert_eq!(guids, ["old", "absent", "seen"]);
+
+        history.set_retention(Retention {
+            max_age: Some(u64::MAX),
+            ..Default::default()
+        });
+        assert!(history.prune(now, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn import_toml_once() {
+        let dir = TempDir::new("import");
+        let path = |file: &str| dir.file(file);
+        std::fs::write(path("history.toml"), "downloaded = [\"a\"]\n").unwrap();
+        let state_path = path("state.toml");
+        let mut state = State::new(&state_path, false).unwrap();
+        let episode = Episode::new("b".to_string(), None, "b.torrent".to_string());
+        state.download_list_mut().push(episode);
+        SyncFile::sync(&mut state).unwrap();
+
+        import_toml(&path("arni.db"), &path("history.toml"), &path("state.toml")).unwrap();
+        let history = SqliteHistory::new(&path("arni.db"), false).unwrap();
+        assert!(history.query("a"));
+        let mut state = SqliteState::new(&path("arni.db"), false).unwrap();
+        assert_eq!(state.download_list()[0].guid, "b");
+
+        // only into an empty database
+        state.download_list_mut().clear();
+        state.sync().unwrap();
+        std::fs::write(path("history.toml"), "downloaded = [\"a\", \"c\"]\n").unwrap();
+        import_toml(&path("arni.db"), &path("history.toml"), &path("state.toml")).unwrap();
+        let history = SqliteHistory::new(&path("arni.db"), false).unwrap();
+        assert!(!history.query("c"));
+    }
+
+    #[test]
+    fn download_list_in_database() {
+        let dir = TempDir::new("sqlite");
+        let path = dir.file("arni.db");
+
+        let mut state = SqliteState::new(&path, false).unwrap();
+        let mut episode = Episode::new("a".to_string(), None, "a.torrent".to_string());
+        episode.gid = Some("2089b05ecca3d829".to_string());
+        episode.set_sent();
+        state.download_list_mut().push(episode);
+        state.sync().unwrap();
+
+        let state = SqliteState::new(&path, false).unwrap();
+        let episode = &state.download_list()[0];
+        assert!(episode.is_sent());
+        assert_eq!(episode.gid.as_deref(), Some("2089b05ecca3d829"));
+    }
+}
@@ -0,0 +1,107 @@
+use std::{fs::File, io, path::Path};
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{episode::Episode, file::write_atomic, SyncFile};
+
+/// Episodes that have been picked up but not yet landed in history.
+///
+/// The file is owned by Arni, so the in-memory list always wins over the on disk one.
+pub struct State<'a> {
+    /// Contents as of the last sync
+    snapshot: String,
+    path: &'a Path,
+    inner: SerdeState,
+}
+
+impl<'a> State<'a> {
+    /// With `dry_run` nothing is written, a missing file is only assumed to be the default.
+    pub fn new(path: &'a str, dry_run: bool) -> Result<Self> {
+        let path = Path::new(path);
+        let (inner, snapshot) = if path.exists() {
+            let file = File::open(path).with_context(|| "Fail to open path.")?;
+            let file =
+                io::read_to_string(file).with_context(|| "Fail to read on disk state file.")?;
+            let ret = toml::from_str(&file).with_context(|| "Fail to parse state file.")?;
+            (ret, file)
+        } else {
+            let ret = SerdeState::default();
+            let file = toml::to_string_pretty(&ret)?;
+            if !dry_run {
+                write_atomic(path, file.as_bytes())
+                    .with_context(|| "Fail to create state file.")?;
+            }
+            (ret, file)
+        };
+
+        Ok(Self {
+            snapshot,
+            path,
+            inner,
+        })
+    }
+
+    pub fn download_list(&self) -> &Vec<Episode> {
+        &self.inner.downloading
+    }
+
+    pub fn download_list_mut(&mut self) -> &mut Vec<Episode> {
+        &mut self.inner.downloading
+    }
+}
+
+impl SyncFile for State<'_> {
+    fn path(&self) -> &Path {
+        self.path
+    }
+
+    fn snapshot(&self) -> &str {
+        &self.snapshot
+    }
+
+    fn set_snapshot(&mut self, contents: String) {
+        self.snapshot = contents;
+    }
+
+    fn merge(&mut self, _base: &str, _on_disk: &str) -> Result<()> {
+        warn!("State file has been modified outside Arni, overwriting it.");
+        Ok(())
+    }
+
+    fn serialize(&mut self) -> Result<String> {
+        Ok(toml::to_string_pretty(&self.inner)?)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SerdeState {
+    downloading: Vec<Episode>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::episode::DownloadStatus;
+
+    #[test]
+    fn round_trip() {
+        let mut episode = Episode::new(
+            "guid".to_string(),
+            Some("title".to_string()),
+            "https://example.com/a.torrent".to_string(),
+        );
+        episode.gid = Some("2089b05ecca3d829".to_string());
+        episode.set_sent();
+        let state = SerdeState {
+            downloading: vec![episode],
+        };
+
+        let toml = toml::to_string_pretty(&state).unwrap();
+        let state: SerdeState = toml::from_str(&toml).unwrap();
+        let episode = &state.downloading[0];
+        assert_eq!(episode.gid.as_deref(), Some("2089b05ecca3d829"));
+        assert_eq!(episode.download_status, DownloadStatus::Sent);
+    }
+}
@@ -0,0 +1,102 @@
+//! Storage of history and the download list, behind traits so the backend can be picked in
+//! config.
+//!
+//! TOML files are the default, see [`super::sqlite`] for the SQLite backend.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    config::Retention,
+    episode::Episode,
+    history::{History, HistoryRecord},
+    state::State,
+    SyncFile,
+};
+
+/// Which backend stores history and the download list, `storage` in config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    /// `history.toml` and `state.toml`
+    #[default]
+    Toml,
+    /// `arni.db`
+    Sqlite,
+}
+
+/// Records of episodes that left the download list.
+pub trait HistoryStore {
+    /// Whether the episode has been downloaded, removed or given up on.
+    fn query(&self, guid: &str) -> bool;
+    /// Latest record of an episode.
+    fn get(&self, guid: &str) -> Option<HistoryRecord>;
+    fn push(&mut self, record: HistoryRecord);
+    /// Note that a recorded episode still shows in a feed.
+    fn seen(&mut self, guid: &str, now: DateTime<Utc>);
+    /// Note that a feed was not fetched, so its recorded episodes count as still showing.
+    fn seen_feed(&mut self, feed: &str, now: DateTime<Utc>);
+    fn set_retention(&mut self, retention: Retention);
+    /// Remove records expired under the retention policy, returns them.
+    ///
+    /// With `dry_run` nothing is removed, expired records are only returned.
+    fn prune(&mut self, now: DateTime<Utc>, dry_run: bool) -> Result<Vec<HistoryRecord>>;
+    /// Persist pushed records and pick up changes made by others.
+    fn sync(&mut self) -> Result<()>;
+}
+
+/// The persisted download list.
+pub trait StateStore {
+    fn download_list(&self) -> &Vec<Episode>;
+    fn download_list_mut(&mut self) -> &mut Vec<Episode>;
+    fn sync(&mut self) -> Result<()>;
+}
+
+impl HistoryStore for History<'_> {
+    fn query(&self, guid: &str) -> bool {
+        History::query(self, guid)
+    }
+
+    fn get(&self, guid: &str) -> Option<HistoryRecord> {
+        History::get(self, guid).cloned()
+    }
+
+    fn push(&mut self, record: HistoryRecord) {
+        History::push(self, record)
+    }
+
+    fn seen(&mut self, guid: &str, now: DateTime<Utc>) {
+        History::seen(self, guid, now)
+    }
+
+    fn seen_feed(&mut self, feed: &str, now: DateTime<Utc>) {
+        History::seen_feed(self, feed, now)
+    }
+
+    fn set_retention(&mut self, retention: Retention) {
+        History::set_retention(self, retention)
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>, dry_run: bool) -> Result<Vec<HistoryRecord>> {
+        Ok(History::prune(self, now, dry_run))
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        SyncFile::sync(self)
+    }
+}
+
+impl StateStore for State<'_> {
+    fn download_list(&self) -> &Vec<Episode> {
+        State::download_list(self)
+    }
+
+    fn download_list_mut(&mut self) -> &mut Vec<Episode> {
+        State::download_list_mut(self)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        SyncFile::sync(self)
+    }
+}
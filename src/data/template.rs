@@ -0,0 +1,150 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Datelike};
+
+use super::episode::Episode;
+
+/// Placeholder value used when an episode lacks the metadata
+const UNKNOWN: &str = "unknown";
+
+/// Metadata a download path template is expanded from.
+pub struct TemplateContext<'a> {
+    /// Feed name in config
+    pub feed: &'a str,
+    /// Title of the feed, e.g. the rss channel title
+    pub channel: &'a str,
+    pub episode: &'a Episode,
+}
+
+impl TemplateContext<'_> {
+    fn value(&self, key: &str) -> Result<String> {
+        let pub_date = self
+            .episode
+            .pub_date
+            .as_deref()
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok());
+        let title = self.episode.title.as_deref().unwrap_or(UNKNOWN);
+        let ret = match key {
+            "feed" => self.feed.to_string(),
+            "channel" => self.channel.to_string(),
+            "title" => title.to_string(),
+            "title_sanitized" => strip_tags(title),
+            "category" => match self.episode.categories.first() {
+                Some(category) => category.to_string(),
+                None => UNKNOWN.to_string(),
+            },
+            "year" => match pub_date {
+                Some(date) => format!("{:04}", date.year()),
+                None => UNKNOWN.to_string(),
+            },
+            "month" => match pub_date {
+                Some(date) => format!("{:02}", date.month()),
+                None => UNKNOWN.to_string(),
+            },
+            "day" => match pub_date {
+                Some(date) => format!("{:02}", date.day()),
+                None => UNKNOWN.to_string(),
+            },
+            _ => bail!("Unknown placeholder {{{key}}} in path template"),
+        };
+        Ok(sanitize(&ret))
+    }
+}
+
+/// Expand placeholders like `{feed}/{title_sanitized}/{year}` in a download path.
+///
+/// Available placeholders are `feed`, `channel`, `title`, `title_sanitized`, `category`, `year`,
+/// `month` and `day`. Every expanded value is made safe to be used as one path component.
+pub fn expand(template: &str, context: &TemplateContext) -> Result<String> {
+    let mut ret = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        ret.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            bail!("Unclosed placeholder in path template: {template}");
+        };
+        let key = &rest[start + 1..start + end];
+        ret.push_str(&context.value(key)?);
+        rest = &rest[start + end + 1..];
+    }
+    ret.push_str(rest);
+    Ok(ret)
+}
+
+/// Check the placeholders of a template, without an episode to expand it with.
+pub fn check(template: &str) -> Result<()> {
+    let episode = Episode::new(String::new(), None, String::new());
+    let context = TemplateContext {
+        feed: "",
+        channel: "",
+        episode: &episode,
+    };
+    expand(template, &context).map(|_| ())
+}
+
+/// Make a string safe to be used as one path component on common filesystems.
+pub fn sanitize(component: &str) -> String {
+    let ret: String = component
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let ret = ret.trim().trim_end_matches('.');
+    match ret {
+        "" | "." | ".." => "_".to_string(),
+        ret => ret.to_string(),
+    }
+}
+
+/// Remove bracketed tags like `[Sub]` or `(1080p)` from a title.
+fn strip_tags(title: &str) -> String {
+    let mut ret = String::with_capacity(title.len());
+    let mut depth = 0;
+    for c in title.chars() {
+        match c {
+            '[' | '(' | '【' => depth += 1,
+            ']' | ')' | '】' if depth > 0 => depth -= 1,
+            c if depth == 0 => ret.push(c),
+            _ => {}
+        }
+    }
+    ret.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_template() {
+        let mut episode = Episode::new(
+            "guid".to_string(),
+            Some("[Sub] Show: Part 2 - 01 [1080p]".to_string()),
+            "https://example.com/a.torrent".to_string(),
+        );
+        episode.pub_date = Some("Mon, 02 Oct 2023 10:00:00 +0000".to_string());
+        let context = TemplateContext {
+            feed: "show",
+            channel: "Releases",
+            episode: &episode,
+        };
+
+        let dir = expand("/media/{feed}/{title_sanitized}/{year}-{month}", &context).unwrap();
+        assert_eq!(dir, "/media/show/Show_ Part 2 - 01/2023-10");
+        assert_eq!(
+            expand("/media/{category}", &context).unwrap(),
+            "/media/unknown"
+        );
+        assert!(expand("/media/{nope}", &context).is_err());
+        assert!(expand("/media/{feed", &context).is_err());
+    }
+
+    #[test]
+    fn sanitize_component() {
+        assert_eq!(sanitize("a/b\\c"), "a_b_c");
+        assert_eq!(sanitize(".."), "_");
+        assert_eq!(sanitize(" name. "), "name");
+    }
+}
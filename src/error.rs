@@ -8,6 +8,10 @@ pub enum Error {
     ImpossibleEpisodeState,
     RPCServerError(JsonRPCError),
     Aria2ConnectionError,
+    Aria2Unauthorized,
+    Aria2GidNotFound,
+    /// Another process works in the directory, with its pid if known
+    WorkingDirLocked(String, Option<u32>),
 }
 
 impl std::fmt::Display for Error {
@@ -17,7 +21,15 @@ impl std::fmt::Display for Error {
             Self::JsonRPCNotReady => "jsonrpc not ready".to_string(),
             Self::ImpossibleEpisodeState => "Impossible Episode State".to_string(),
             Self::Aria2ConnectionError => "Can't connect to aria2".to_string(),
+            Self::Aria2Unauthorized => {
+                "aria2 rejected the rpc secret, check aria2_secret in config".to_string()
+            }
+            Self::Aria2GidNotFound => "aria2 can't find the gid".to_string(),
             Self::RPCServerError(e) => format!("{e}"),
+            Self::WorkingDirLocked(dir, Some(pid)) => {
+                format!("{dir} is in use by another arni process ({pid})")
+            }
+            Self::WorkingDirLocked(dir, None) => format!("{dir} is in use by another arni process"),
         };
         write!(f, "{msg}")
     }
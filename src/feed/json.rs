@@ -0,0 +1,143 @@
+//! JSON Feed 1.1, see <https://www.jsonfeed.org/version/1.1/>.
+
+use chrono::DateTime;
+use serde::Deserialize;
+
+use super::{
+    link::{is_magnet, is_torrent},
+    Feed, FeedItem,
+};
+
+const TORRENT_MIME: &str = "application/x-bittorrent";
+
+#[derive(Debug, Deserialize)]
+pub struct JsonFeed {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    items: Vec<JsonItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonItem {
+    id: String,
+    url: Option<String>,
+    title: Option<String>,
+    content_html: Option<String>,
+    content_text: Option<String>,
+    summary: Option<String>,
+    /// RFC 3339
+    date_published: Option<String>,
+    date_modified: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    attachments: Vec<JsonAttachment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonAttachment {
+    url: String,
+    mime_type: Option<String>,
+}
+
+impl From<JsonFeed> for Feed {
+    fn from(value: JsonFeed) -> Self {
+        Self {
+            title: value.title,
+            ttl: None,
+            items: value.items.into_iter().map(FeedItem::from).collect(),
+        }
+    }
+}
+
+impl From<JsonItem> for FeedItem {
+    fn from(value: JsonItem) -> Self {
+        // prefer a torrent attachment, fall back to one linking a torrent or magnet, never
+        // anything else like a cover image
+        let enclosure = value
+            .attachments
+            .iter()
+            .find(|a| a.mime_type.as_deref() == Some(TORRENT_MIME))
+            .or_else(|| {
+                value
+                    .attachments
+                    .iter()
+                    .find(|a| is_torrent(&a.url) || is_magnet(&a.url))
+            })
+            .map(|a| a.url.to_string());
+        let pub_date = value
+            .date_published
+            .or(value.date_modified)
+            .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+            .map(|date| date.to_rfc2822());
+        Self {
+            guid: Some(value.id),
+            title: value.title,
+            link: value.url,
+            enclosure,
+            pub_date,
+            categories: value.tags,
+            description: value.content_html.or(value.summary).or(value.content_text),
+            extensions: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn torrent_attachment() {
+        let json = br#"{
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": "Releases",
+            "items": [{
+                "id": "1",
+                "title": "[Sub] Show - 01 [1080p]",
+                "url": "https://example.com/view/1",
+                "date_published": "2023-10-02T10:00:00+08:00",
+                "tags": ["Anime"],
+                "attachments": [
+                    {"url": "https://example.com/1.jpg", "mime_type": "image/jpeg"},
+                    {"url": "https://example.com/1.torrent", "mime_type": "application/x-bittorrent"}
+                ]
+            }]
+        }"#;
+        let feed = Feed::read_from(json).unwrap();
+        assert_eq!(feed.title, "Releases");
+        let item = &feed.items[0];
+        assert_eq!(item.guid.as_deref(), Some("1"));
+        assert_eq!(
+            item.enclosure.as_deref(),
+            Some("https://example.com/1.torrent")
+        );
+        assert_eq!(
+            item.pub_date.as_deref(),
+            Some("Mon, 2 Oct 2023 10:00:00 +0800")
+        );
+    }
+
+    #[test]
+    fn skip_other_attachments() {
+        let json = br#"{
+            "version": "https://jsonfeed.org/version/1.1",
+            "items": [
+                {"id": "1", "attachments": [
+                    {"url": "https://example.com/1.jpg", "mime_type": "image/jpeg"},
+                    {"url": "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a"}
+                ]},
+                {"id": "2", "attachments": [
+                    {"url": "https://example.com/2.jpg", "mime_type": "image/jpeg"}
+                ]}
+            ]
+        }"#;
+        let feed = Feed::read_from(json).unwrap();
+        assert_eq!(
+            feed.items[0].enclosure.as_deref(),
+            Some("magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a")
+        );
+        assert_eq!(feed.items[1].enclosure, None);
+    }
+}
@@ -0,0 +1,223 @@
+//! Finding the link to download from a feed item.
+//!
+//! Not every feed attaches the torrent as an enclosure, many carry a magnet in `<link>`, in the
+//! description, or in namespaced elements like `torrent:magnetURI` and `nyaa:infoHash`.
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::FeedItem;
+
+/// A place in a feed item to look for the download link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkSource {
+    /// Url of the enclosure or attachment, whatever it is
+    Enclosure,
+    /// `<link>` of the item, if it is a magnet or a `.torrent` file
+    Link,
+    /// First magnet found in the description
+    Description,
+    /// Elements like `torrent:magnetURI`
+    MagnetUri,
+    /// Elements like `nyaa:infoHash` or `torrent:infoHash`, built into a magnet with trackers
+    InfoHash,
+}
+
+impl LinkSource {
+    pub const DEFAULT: [Self; 5] = [
+        Self::Enclosure,
+        Self::MagnetUri,
+        Self::InfoHash,
+        Self::Link,
+        Self::Description,
+    ];
+}
+
+/// Tries each source in order and returns the first link found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkExtractor {
+    sources: Vec<LinkSource>,
+    /// Trackers added to magnets built from an infohash
+    trackers: Vec<String>,
+}
+
+impl Default for LinkExtractor {
+    fn default() -> Self {
+        Self::new(LinkSource::DEFAULT.to_vec(), vec![])
+    }
+}
+
+impl LinkExtractor {
+    pub fn new(sources: Vec<LinkSource>, trackers: Vec<String>) -> Self {
+        Self { sources, trackers }
+    }
+
+    pub fn extract(&self, item: &FeedItem) -> Option<String> {
+        self.sources
+            .iter()
+            .find_map(|source| self.extract_from(*source, item))
+    }
+
+    /// Every distinct link found, in the order of sources.
+    pub fn extract_all(&self, item: &FeedItem) -> Vec<String> {
+        let mut ret: Vec<String> = vec![];
+        for link in self
+            .sources
+            .iter()
+            .filter_map(|source| self.extract_from(*source, item))
+        {
+            if !ret.contains(&link) {
+                ret.push(link);
+            }
+        }
+        ret
+    }
+
+    fn extract_from(&self, source: LinkSource, item: &FeedItem) -> Option<String> {
+        match source {
+            LinkSource::Enclosure => item.enclosure.clone(),
+            LinkSource::Link => item
+                .link
+                .as_deref()
+                .filter(|link| is_magnet(link) || is_torrent(link))
+                .map(|link| link.to_string()),
+            LinkSource::Description => {
+                let description = item.description.as_deref()?;
+                let magnet = magnet_regex().find(description)?;
+                // the description is usually html with escaped `&`
+                Some(magnet.as_str().replace("&amp;", "&"))
+            }
+            LinkSource::MagnetUri => item
+                .extension("magnetURI")
+                .filter(|link| is_magnet(link))
+                .map(|link| link.to_string()),
+            LinkSource::InfoHash => {
+                let hash = item
+                    .extension("infoHash")
+                    .filter(|hash| is_info_hash(hash))?;
+                Some(self.magnet(hash, item.title.as_deref()))
+            }
+        }
+    }
+
+    /// Build a magnet from an infohash, with the title as display name and configured trackers.
+    pub fn magnet(&self, hash: &str, title: Option<&str>) -> String {
+        let mut ret = format!("magnet:?xt=urn:btih:{hash}");
+        if let Some(title) = title {
+            ret.push_str("&dn=");
+            ret.push_str(&encode(title));
+        }
+        for tracker in &self.trackers {
+            ret.push_str("&tr=");
+            ret.push_str(&encode(tracker));
+        }
+        ret
+    }
+}
+
+fn magnet_regex() -> &'static Regex {
+    static MAGNET: OnceLock<Regex> = OnceLock::new();
+    MAGNET.get_or_init(|| Regex::new(r#"magnet:\?[^\s"'<>]+"#).unwrap())
+}
+
+pub(crate) fn is_magnet(link: &str) -> bool {
+    link.starts_with("magnet:?")
+}
+
+pub(crate) fn is_torrent(link: &str) -> bool {
+    let path = link.split(['?', '#']).next().unwrap_or_default();
+    path.to_ascii_lowercase().ends_with(".torrent")
+}
+
+/// Hex encoded SHA-1 or base32 encoded btih.
+fn is_info_hash(hash: &str) -> bool {
+    match hash.len() {
+        40 => hash.chars().all(|c| c.is_ascii_hexdigit()),
+        32 => hash
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || ('2'..='7').contains(&c)),
+        _ => false,
+    }
+}
+
+/// Percent-encode everything but unreserved characters.
+fn encode(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                ret.push(b as char)
+            }
+            b => ret.push_str(&format!("%{b:02X}")),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "1dd6e3a8b2a4b5c0f8e5d3c6a9b8e7f6d5c4b3a2";
+
+    #[test]
+    fn extraction_chain() {
+        let mut item = FeedItem {
+            link: Some("https://example.com/view/1".to_string()),
+            description: Some(format!(
+                r#"<a href="magnet:?xt=urn:btih:{HASH}&amp;dn=a">magnet</a>"#
+            )),
+            ..Default::default()
+        };
+        let extractor = LinkExtractor::default();
+        assert_eq!(
+            extractor.extract(&item),
+            Some(format!("magnet:?xt=urn:btih:{HASH}&dn=a"))
+        );
+
+        item.link = Some("https://example.com/1.torrent?key=1".to_string());
+        assert_eq!(
+            extractor.extract(&item).as_deref(),
+            Some("https://example.com/1.torrent?key=1")
+        );
+
+        assert_eq!(
+            extractor.extract_all(&item),
+            vec![
+                "https://example.com/1.torrent?key=1".to_string(),
+                format!("magnet:?xt=urn:btih:{HASH}&dn=a")
+            ]
+        );
+
+        let extractor = LinkExtractor::new(vec![LinkSource::Enclosure], vec![]);
+        assert_eq!(extractor.extract(&item), None);
+    }
+
+    #[test]
+    fn magnet_from_info_hash() {
+        let mut item = FeedItem {
+            title: Some("Show - 01".to_string()),
+            ..Default::default()
+        };
+        item.extensions
+            .insert("nyaa:infoHash".to_string(), HASH.to_string());
+        let extractor = LinkExtractor::new(
+            vec![LinkSource::InfoHash],
+            vec!["udp://tracker.example.com:1337/announce".to_string()],
+        );
+        assert_eq!(
+            extractor.extract(&item),
+            Some(format!(
+                "magnet:?xt=urn:btih:{HASH}&dn=Show%20-%2001\
+                 &tr=udp%3A%2F%2Ftracker.example.com%3A1337%2Fannounce"
+            ))
+        );
+
+        item.extensions
+            .insert("nyaa:infoHash".to_string(), "not a hash".to_string());
+        assert_eq!(extractor.extract(&item), None);
+    }
+}
@@ -0,0 +1,268 @@
+//! Feed formats normalized into one shape.
+//!
+//! RSS 2.0, Atom and JSON Feed 1.1 are supported, the format is detected from the content.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+
+mod json;
+pub mod link;
+
+/// A fetched feed, e.g. an rss channel.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Feed {
+    pub title: String,
+    /// Minutes the feed may be cached, from rss `<ttl>`
+    pub ttl: Option<u64>,
+    pub items: Vec<FeedItem>,
+}
+
+/// One entry of a feed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeedItem {
+    pub guid: Option<String>,
+    pub title: Option<String>,
+    /// Link to the web page of the item
+    pub link: Option<String>,
+    /// Url of the attached file, which is the torrent for most feeds we care about
+    pub enclosure: Option<String>,
+    /// Publish date in RFC 2822
+    pub pub_date: Option<String>,
+    pub categories: Vec<String>,
+    pub description: Option<String>,
+    /// Namespaced elements like `nyaa:infoHash`, keyed by the prefixed name
+    pub extensions: BTreeMap<String, String>,
+}
+
+impl FeedItem {
+    /// Value of the first namespaced element with the local `name`, whatever the prefix is.
+    pub fn extension(&self, name: &str) -> Option<&str> {
+        self.extensions
+            .iter()
+            .find(|(key, _)| key.rsplit(':').next() == Some(name))
+            .map(|(_, value)| value.trim())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl FeedFormat {
+    /// Guess the format from the first meaningful bytes of the content.
+    pub fn detect(content: &[u8]) -> Option<Self> {
+        let content = String::from_utf8_lossy(content);
+        let content = content.trim_start_matches('\u{feff}').trim_start();
+        if content.starts_with('{') {
+            return Some(Self::Json);
+        }
+
+        // skip xml declaration, comments and doctype to find the root element
+        let mut rest = content;
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start + 1..];
+            if rest.starts_with('?') || rest.starts_with('!') {
+                continue;
+            }
+            let name: String = rest
+                .chars()
+                .take_while(|c| !c.is_whitespace() && *c != '>' && *c != '/')
+                .collect();
+            // root element may be namespaced, e.g. `atom:feed`
+            let name = name.rsplit(':').next().unwrap_or_default();
+            return match name {
+                "rss" => Some(Self::Rss),
+                "feed" => Some(Self::Atom),
+                _ => None,
+            };
+        }
+        None
+    }
+}
+
+impl Feed {
+    pub fn read_from(content: &[u8]) -> Result<Self> {
+        match FeedFormat::detect(content) {
+            Some(FeedFormat::Rss) => {
+                let channel = rss::Channel::read_from(content)?;
+                Ok(channel.into())
+            }
+            Some(FeedFormat::Atom) => {
+                let feed = atom_syndication::Feed::read_from(content)?;
+                Ok(feed.into())
+            }
+            Some(FeedFormat::Json) => {
+                let feed: json::JsonFeed =
+                    serde_json::from_slice(content).with_context(|| "Fail to parse JSON Feed.")?;
+                Ok(feed.into())
+            }
+            None => anyhow::bail!("Unknown feed format"),
+        }
+    }
+}
+
+impl From<rss::Channel> for Feed {
+    fn from(value: rss::Channel) -> Self {
+        Self {
+            title: value.title().to_string(),
+            ttl: value.ttl().and_then(|ttl| ttl.parse().ok()),
+            items: value.into_items().into_iter().map(FeedItem::from).collect(),
+        }
+    }
+}
+
+impl From<rss::Item> for FeedItem {
+    fn from(value: rss::Item) -> Self {
+        Self {
+            guid: value.guid().map(|guid| guid.value().to_string()),
+            title: value.title().map(|title| title.to_string()),
+            link: value.link().map(|link| link.to_string()),
+            enclosure: value
+                .enclosure()
+                .map(|enclosure| enclosure.url().to_string()),
+            pub_date: value.pub_date().map(|date| date.to_string()),
+            categories: value
+                .categories()
+                .iter()
+                .map(|category| category.name().to_string())
+                .collect(),
+            description: value.description().map(|d| d.to_string()),
+            extensions: flatten_extensions(value.extensions(), |e| e.value()),
+        }
+    }
+}
+
+impl From<atom_syndication::Feed> for Feed {
+    fn from(value: atom_syndication::Feed) -> Self {
+        Self {
+            title: value.title().to_string(),
+            ttl: None,
+            items: value.entries().iter().map(FeedItem::from).collect(),
+        }
+    }
+}
+
+impl From<&atom_syndication::Entry> for FeedItem {
+    fn from(value: &atom_syndication::Entry) -> Self {
+        let link = |rel: &str| {
+            value
+                .links()
+                .iter()
+                .find(|link| link.rel() == rel)
+                .map(|link| link.href().to_string())
+        };
+        let description = match value.summary() {
+            Some(summary) => Some(summary.to_string()),
+            None => value
+                .content()
+                .and_then(|content| content.value())
+                .map(|v| v.to_string()),
+        };
+        Self {
+            guid: Some(value.id().to_string()),
+            title: Some(value.title().to_string()),
+            link: link("alternate"),
+            enclosure: link("enclosure"),
+            pub_date: Some(value.published().unwrap_or(value.updated()).to_rfc2822()),
+            categories: value
+                .categories()
+                .iter()
+                .map(|category| category.term().to_string())
+                .collect(),
+            description,
+            extensions: flatten_extensions(value.extensions(), |e| e.value()),
+        }
+    }
+}
+
+/// Keep the first value of each namespaced element, rss and atom extension maps look the same.
+fn flatten_extensions<E>(
+    extensions: &BTreeMap<String, BTreeMap<String, Vec<E>>>,
+    value: impl Fn(&E) -> Option<&str>,
+) -> BTreeMap<String, String> {
+    let mut ret = BTreeMap::new();
+    for (prefix, elements) in extensions {
+        for (name, values) in elements {
+            if let Some(value) = values.iter().find_map(&value) {
+                ret.insert(format!("{prefix}:{name}"), value.to_string());
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_format() {
+        let rss = br#"<?xml version="1.0"?><!-- comment --><rss version="2.0"></rss>"#;
+        assert_eq!(FeedFormat::detect(rss), Some(FeedFormat::Rss));
+        let atom = br#"<?xml version="1.0"?><feed xmlns="http://www.w3.org/2005/Atom"></feed>"#;
+        assert_eq!(FeedFormat::detect(atom), Some(FeedFormat::Atom));
+        let json = br#"  {"version": "https://jsonfeed.org/version/1.1"}"#;
+        assert_eq!(FeedFormat::detect(json), Some(FeedFormat::Json));
+        assert_eq!(FeedFormat::detect(b"<html></html>"), None);
+    }
+
+    #[test]
+    fn read_atom() {
+        let atom = br#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Releases</title>
+  <id>urn:uuid:60a76c80-d399-11d9-b93C-0003939e0af6</id>
+  <updated>2023-10-02T10:00:00Z</updated>
+  <entry>
+    <title>[Sub] Show - 01 [1080p]</title>
+    <id>urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6a</id>
+    <updated>2023-10-02T10:00:00Z</updated>
+    <link rel="alternate" href="https://example.com/view/1"/>
+    <link rel="enclosure" type="application/x-bittorrent" href="https://example.com/1.torrent"/>
+    <category term="Anime"/>
+  </entry>
+</feed>"#;
+        let feed = Feed::read_from(atom).unwrap();
+        assert_eq!(feed.title, "Releases");
+        let item = &feed.items[0];
+        assert_eq!(
+            item.enclosure.as_deref(),
+            Some("https://example.com/1.torrent")
+        );
+        assert_eq!(item.link.as_deref(), Some("https://example.com/view/1"));
+        assert_eq!(
+            item.pub_date.as_deref(),
+            Some("Mon, 2 Oct 2023 10:00:00 +0000")
+        );
+        assert_eq!(item.categories, vec!["Anime".to_string()]);
+    }
+
+    #[test]
+    fn rss_extensions() {
+        let rss = br#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:nyaa="https://nyaa.si/xmlns/nyaa">
+  <channel>
+    <title>Nyaa</title>
+    <link>https://nyaa.si/</link>
+    <description>Releases</description>
+    <item>
+      <title>[Sub] Show - 01 [1080p]</title>
+      <link>https://nyaa.si/download/1.torrent</link>
+      <guid isPermaLink="true">https://nyaa.si/view/1</guid>
+      <nyaa:infoHash>1dd6e3a8b2a4b5c0f8e5d3c6a9b8e7f6d5c4b3a2</nyaa:infoHash>
+    </item>
+  </channel>
+</rss>"#;
+        let feed = Feed::read_from(rss).unwrap();
+        let item = &feed.items[0];
+        assert_eq!(item.enclosure, None);
+        assert_eq!(
+            item.extension("infoHash"),
+            Some("1dd6e3a8b2a4b5c0f8e5d3c6a9b8e7f6d5c4b3a2")
+        );
+    }
+}
@@ -0,0 +1,580 @@
+use crate::error::Error;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt::Formatter;
+
+pub mod types;
+
+#[derive(Debug)]
+pub enum JsonRPCError {
+    ParseError,
+    InvalidRequest,
+    MethodNotFound,
+    InvalidParams,
+    InternalError,
+    ServerError,
+    OtherError,
+    NotStandardResponse,
+}
+
+impl std::fmt::Display for JsonRPCError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let msg = match &self {
+            Self::ParseError => "parse error",
+            Self::InvalidRequest => "invalid request",
+            Self::MethodNotFound => "method not found",
+            Self::InvalidParams => "invalid params",
+            Self::InternalError => "internal error",
+            Self::ServerError => "server error",
+            Self::OtherError => "application custom error",
+            Self::NotStandardResponse => "server returns non-standard response",
+        };
+        write!(f, "{msg}")
+    }
+}
+
+impl std::error::Error for JsonRPCError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+/// aria2 input options like `dir` or `max-download-limit`, values are always strings in aria2 rpc.
+pub type Aria2Options = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonRPCMethod {
+    AddUri,
+    AddTorrent,
+    AddMetalink,
+    Remove,
+    ForceRemove,
+    Pause,
+    Unpause,
+    TellStatus,
+    TellActive,
+    TellWaiting,
+    TellStopped,
+    GetFiles,
+    GetPeers,
+    ChangeOption,
+    GetGlobalStat,
+    RemoveDownloadResult,
+    PurgeDownloadResult,
+    GetVersion,
+    Multicall,
+    ListMethods,
+}
+
+impl JsonRPCMethod {
+    const ALL: [(Self, &'static str); 20] = [
+        (Self::AddUri, "aria2.addUri"),
+        (Self::AddTorrent, "aria2.addTorrent"),
+        (Self::AddMetalink, "aria2.addMetalink"),
+        (Self::Remove, "aria2.remove"),
+        (Self::ForceRemove, "aria2.forceRemove"),
+        (Self::Pause, "aria2.pause"),
+        (Self::Unpause, "aria2.unpause"),
+        (Self::TellStatus, "aria2.tellStatus"),
+        (Self::TellActive, "aria2.tellActive"),
+        (Self::TellWaiting, "aria2.tellWaiting"),
+        (Self::TellStopped, "aria2.tellStopped"),
+        (Self::GetFiles, "aria2.getFiles"),
+        (Self::GetPeers, "aria2.getPeers"),
+        (Self::ChangeOption, "aria2.changeOption"),
+        (Self::GetGlobalStat, "aria2.getGlobalStat"),
+        (Self::RemoveDownloadResult, "aria2.removeDownloadResult"),
+        (Self::PurgeDownloadResult, "aria2.purgeDownloadResult"),
+        (Self::GetVersion, "aria2.getVersion"),
+        (Self::Multicall, "system.multicall"),
+        (Self::ListMethods, "system.listMethods"),
+    ];
+
+    /// Method name on the wire, e.g. `aria2.addUri`.
+    pub fn as_str(&self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(method, _)| method == self)
+            .map(|(_, name)| *name)
+            .unwrap_or_default()
+    }
+
+    /// Whether the method queues a new download.
+    pub fn is_add(&self) -> bool {
+        matches!(self, Self::AddUri | Self::AddTorrent | Self::AddMetalink)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(method, _)| *method)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonRPC {
+    jsonrpc: String,
+    method: Option<String>,
+    id: Option<String>,
+    params: Option<serde_json::Value>,
+}
+
+impl JsonRPC {
+    fn new(ua: &str) -> Self {
+        let id = ua.to_string();
+        JsonRPC {
+            jsonrpc: "2.0".to_string(),
+            method: None,
+            id: Some(id),
+            params: None,
+        }
+    }
+
+    pub fn builder(ua: &str) -> JsonRPCBuilder {
+        JsonRPCBuilder::new(ua)
+    }
+
+    pub fn to_string(&self) -> Result<String> {
+        let ret = serde_json::to_string(&self)?;
+        Ok(ret)
+    }
+
+    pub fn get_method(&self) -> Result<JsonRPCMethod> {
+        match &self.method {
+            Some(method) => JsonRPCMethod::from_name(method)
+                .ok_or_else(|| anyhow::Error::from(JsonRPCError::MethodNotFound)),
+            None => Err(anyhow::Error::from(Error::JsonRPCNotReady)),
+        }
+    }
+
+    /// Whether sending this twice would queue the same download twice, that is an add method or
+    /// a multicall containing one.
+    pub fn adds_downloads(&self) -> bool {
+        match self.get_method() {
+            Ok(JsonRPCMethod::Multicall) => self
+                .params
+                .as_ref()
+                .and_then(|params| params.get(0))
+                .and_then(|calls| calls.as_array())
+                .is_some_and(|calls| {
+                    calls.iter().any(|call| {
+                        call["methodName"]
+                            .as_str()
+                            .and_then(JsonRPCMethod::from_name)
+                            .is_some_and(|method| method.is_add())
+                    })
+                }),
+            Ok(method) => method.is_add(),
+            Err(_) => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonRPCBuilder {
+    inner: JsonRPC,
+    available: bool,
+}
+
+impl JsonRPCBuilder {
+    pub fn new(ua: &str) -> Self {
+        let inner = JsonRPC::new(ua);
+        Self {
+            inner,
+            available: false,
+        }
+    }
+
+    pub fn build(self) -> Result<JsonRPC> {
+        if !self.available {
+            return Err(anyhow::Error::from(Error::JsonRPCNotReady));
+        }
+        Ok(self.inner)
+    }
+
+    fn parse_token(secret: Option<String>) -> String {
+        match secret {
+            Some(s) => format!("token:{s}"),
+            None => "token:".to_string(),
+        }
+    }
+
+    pub fn aria2_add_uri(
+        mut self,
+        secret: Option<String>,
+        uri: &str,
+        options: Option<&Aria2Options>,
+    ) -> Self {
+        let secret = Self::parse_token(secret);
+        let params = match options {
+            Some(options) if !options.is_empty() => json!([secret, vec![uri], options]),
+            _ => json!([secret, vec![uri]]),
+        };
+        self.complete_method(JsonRPCMethod::AddUri, params);
+        self
+    }
+
+    /// `torrent` is the content of a .torrent file, `uris` are used for web-seeding.
+    pub fn aria2_add_torrent(
+        mut self,
+        secret: Option<String>,
+        torrent: &[u8],
+        uris: &[&str],
+        options: Option<&Aria2Options>,
+    ) -> Self {
+        let secret = Self::parse_token(secret);
+        let torrent = BASE64.encode(torrent);
+        let options = options.cloned().unwrap_or_default();
+        let params = json!([secret, torrent, uris, options]);
+        self.complete_method(JsonRPCMethod::AddTorrent, params);
+        self
+    }
+
+    /// `metalink` is the content of a .metalink file.
+    pub fn aria2_add_metalink(
+        mut self,
+        secret: Option<String>,
+        metalink: &[u8],
+        options: Option<&Aria2Options>,
+    ) -> Self {
+        let secret = Self::parse_token(secret);
+        let metalink = BASE64.encode(metalink);
+        let options = options.cloned().unwrap_or_default();
+        let params = json!([secret, metalink, options]);
+        self.complete_method(JsonRPCMethod::AddMetalink, params);
+        self
+    }
+
+    pub fn aria2_remove(self, secret: Option<String>, gid: &str) -> Self {
+        self.gid_method(JsonRPCMethod::Remove, secret, gid)
+    }
+
+    pub fn aria2_force_remove(self, secret: Option<String>, gid: &str) -> Self {
+        self.gid_method(JsonRPCMethod::ForceRemove, secret, gid)
+    }
+
+    pub fn aria2_pause(self, secret: Option<String>, gid: &str) -> Self {
+        self.gid_method(JsonRPCMethod::Pause, secret, gid)
+    }
+
+    pub fn aria2_unpause(self, secret: Option<String>, gid: &str) -> Self {
+        self.gid_method(JsonRPCMethod::Unpause, secret, gid)
+    }
+
+    pub fn aria2_get_version(mut self, secret: Option<String>) -> Self {
+        let secret = Self::parse_token(secret);
+        let params = json!([secret]);
+        self.complete_method(JsonRPCMethod::GetVersion, params);
+        self
+    }
+
+    pub fn aria2_tell_status(mut self, secret: Option<String>, gid: &str) -> Self {
+        let secret = Self::parse_token(secret);
+        let gid = gid.to_string();
+        let params = json!([secret, gid]);
+        self.complete_method(JsonRPCMethod::TellStatus, params);
+        self
+    }
+
+    pub fn aria2_tell_active(mut self, secret: Option<String>) -> Self {
+        let secret = Self::parse_token(secret);
+        let params = json!([secret]);
+        self.complete_method(JsonRPCMethod::TellActive, params);
+        self
+    }
+
+    /// `offset` may be negative to count from the end of the queue.
+    pub fn aria2_tell_waiting(mut self, secret: Option<String>, offset: i64, num: u64) -> Self {
+        let secret = Self::parse_token(secret);
+        let params = json!([secret, offset, num]);
+        self.complete_method(JsonRPCMethod::TellWaiting, params);
+        self
+    }
+
+    /// `offset` may be negative to count from the end of the queue.
+    pub fn aria2_tell_stopped(mut self, secret: Option<String>, offset: i64, num: u64) -> Self {
+        let secret = Self::parse_token(secret);
+        let params = json!([secret, offset, num]);
+        self.complete_method(JsonRPCMethod::TellStopped, params);
+        self
+    }
+
+    pub fn aria2_get_files(self, secret: Option<String>, gid: &str) -> Self {
+        self.gid_method(JsonRPCMethod::GetFiles, secret, gid)
+    }
+
+    pub fn aria2_get_peers(self, secret: Option<String>, gid: &str) -> Self {
+        self.gid_method(JsonRPCMethod::GetPeers, secret, gid)
+    }
+
+    pub fn aria2_change_option(
+        mut self,
+        secret: Option<String>,
+        gid: &str,
+        options: &Aria2Options,
+    ) -> Self {
+        let secret = Self::parse_token(secret);
+        let params = json!([secret, gid, options]);
+        self.complete_method(JsonRPCMethod::ChangeOption, params);
+        self
+    }
+
+    pub fn aria2_get_global_stat(mut self, secret: Option<String>) -> Self {
+        let secret = Self::parse_token(secret);
+        let params = json!([secret]);
+        self.complete_method(JsonRPCMethod::GetGlobalStat, params);
+        self
+    }
+
+    pub fn aria2_remove_download_result(self, secret: Option<String>, gid: &str) -> Self {
+        self.gid_method(JsonRPCMethod::RemoveDownloadResult, secret, gid)
+    }
+
+    pub fn aria2_purge_download_result(mut self, secret: Option<String>) -> Self {
+        let secret = Self::parse_token(secret);
+        let params = json!([secret]);
+        self.complete_method(JsonRPCMethod::PurgeDownloadResult, params);
+        self
+    }
+
+    /// Bundle several calls into one request, each of them carries its own secret token.
+    pub fn system_multicall(mut self, calls: Vec<JsonRPC>) -> Self {
+        let calls: Vec<serde_json::Value> = calls
+            .into_iter()
+            .map(|call| json!({"methodName": call.method, "params": call.params}))
+            .collect();
+        let params = json!([calls]);
+        self.complete_method(JsonRPCMethod::Multicall, params);
+        self
+    }
+
+    /// aria2 never asks a secret token for this method.
+    pub fn system_list_methods(mut self) -> Self {
+        self.complete_method(JsonRPCMethod::ListMethods, json!([]));
+        self
+    }
+
+    fn gid_method(mut self, method: JsonRPCMethod, secret: Option<String>, gid: &str) -> Self {
+        let secret = Self::parse_token(secret);
+        let params = json!([secret, gid]);
+        self.complete_method(method, params);
+        self
+    }
+
+    fn complete_method(&mut self, method: JsonRPCMethod, params: serde_json::Value) {
+        self.inner.method = Some(method.as_str().to_string());
+        self.inner.params = Some(params);
+        self.available = true;
+    }
+}
+
+pub struct JsonRPCResponse {
+    pub value: serde_json::Value,
+    pub method: JsonRPCMethod,
+}
+
+impl JsonRPCResponse {
+    /// Deserialize the result into one of the types in [`types`], or `String` for gids.
+    ///
+    /// A result that doesn't fit into `T` is reported as [`JsonRPCError::NotStandardResponse`].
+    pub fn result<T: DeserializeOwned>(self) -> Result<T> {
+        self.check_error()?;
+        match self.value.get("result") {
+            Some(v) => T::deserialize(v).context(JsonRPCError::NotStandardResponse),
+            None => Err(anyhow::Error::from(JsonRPCError::NotStandardResponse)),
+        }
+    }
+
+    fn check_error(&self) -> Result<()> {
+        if let Some(v) = &self.value.get("error") {
+            let code = v.get("code").and_then(|c| c.as_i64());
+            let message = v.get("message").and_then(|m| m.as_str());
+            return match code {
+                Some(code) => Err(rpc_error(code, message.unwrap_or_default())),
+                None => Err(anyhow::Error::from(JsonRPCError::NotStandardResponse)),
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Map an error object returned by aria2 into [`Error`] or [`JsonRPCError`].
+fn rpc_error(code: i64, message: &str) -> anyhow::Error {
+    // aria2 answers a wrong or missing secret token with this message
+    if message == "Unauthorized" {
+        return anyhow::Error::from(Error::Aria2Unauthorized);
+    }
+    // e.g. "GID 2089b05ecca3d829 is not found"
+    if message.starts_with("GID ") && message.ends_with(" is not found") {
+        return anyhow::Error::from(Error::Aria2GidNotFound);
+    }
+    let error = match code {
+        -32700 => JsonRPCError::ParseError,
+        -32600 => JsonRPCError::InvalidRequest,
+        -32601 => JsonRPCError::MethodNotFound,
+        -32602 => JsonRPCError::InvalidParams,
+        -32603 => JsonRPCError::InternalError,
+        -32099..=-32000 => JsonRPCError::ServerError,
+        _ => JsonRPCError::OtherError,
+    };
+    anyhow::Error::from(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_in_params() {
+        let jsonrpc = JsonRPCBuilder::new("arni")
+            .aria2_get_version(Some("secret".to_string()))
+            .build()
+            .unwrap();
+        assert_eq!(jsonrpc.params, Some(json!(["token:secret"])));
+    }
+
+    #[test]
+    fn add_uri_with_options() {
+        let options = Aria2Options::from([("dir".to_string(), "/downloads/show".to_string())]);
+        let jsonrpc = JsonRPCBuilder::new("arni")
+            .aria2_add_uri(None, "magnet:?xt=urn:btih:abc", Some(&options))
+            .build()
+            .unwrap();
+        assert_eq!(
+            jsonrpc.params,
+            Some(json!([
+                "token:",
+                ["magnet:?xt=urn:btih:abc"],
+                {"dir": "/downloads/show"}
+            ]))
+        );
+
+        let jsonrpc = JsonRPCBuilder::new("arni")
+            .aria2_add_uri(None, "magnet:?xt=urn:btih:abc", Some(&Aria2Options::new()))
+            .build()
+            .unwrap();
+        assert_eq!(
+            jsonrpc.params,
+            Some(json!(["token:", ["magnet:?xt=urn:btih:abc"]]))
+        );
+    }
+
+    #[test]
+    fn method_names() {
+        for (method, name) in JsonRPCMethod::ALL {
+            assert_eq!(method.as_str(), name);
+            assert_eq!(JsonRPCMethod::from_name(name), Some(method));
+        }
+        assert_eq!(JsonRPCMethod::from_name("aria2.shutdown"), None);
+    }
+
+    #[test]
+    fn multicall_params() {
+        let calls = vec![
+            JsonRPCBuilder::new("arni")
+                .aria2_pause(Some("s".to_string()), "gid1")
+                .build()
+                .unwrap(),
+            JsonRPCBuilder::new("arni")
+                .aria2_get_global_stat(Some("s".to_string()))
+                .build()
+                .unwrap(),
+        ];
+        let jsonrpc = JsonRPCBuilder::new("arni")
+            .system_multicall(calls)
+            .build()
+            .unwrap();
+        assert!(matches!(jsonrpc.get_method(), Ok(JsonRPCMethod::Multicall)));
+        assert_eq!(
+            jsonrpc.params,
+            Some(json!([[
+                {"methodName": "aria2.pause", "params": ["token:s", "gid1"]},
+                {"methodName": "aria2.getGlobalStat", "params": ["token:s"]}
+            ]]))
+        );
+    }
+
+    #[test]
+    fn typed_result() {
+        let response = JsonRPCResponse {
+            value: json!({
+                "id": "arni",
+                "jsonrpc": "2.0",
+                "result": {"downloadSpeed": "21846", "numActive": "2"}
+            }),
+            method: JsonRPCMethod::GetGlobalStat,
+        };
+        let stat: types::Aria2GlobalStat = response.result().unwrap();
+        assert_eq!(stat.download_speed, 21846);
+        assert_eq!(stat.num_active, 2);
+    }
+
+    #[test]
+    fn malformed_response() {
+        let response = |value| JsonRPCResponse {
+            value,
+            method: JsonRPCMethod::TellStatus,
+        };
+        let error = response(json!({"result": {"totalLength": 42}}))
+            .result::<types::Aria2Status>()
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(JsonRPCError::NotStandardResponse)
+        ));
+        let error = response(json!({"error": {"code": "1"}}))
+            .result::<types::Aria2Status>()
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(JsonRPCError::NotStandardResponse)
+        ));
+        let error = response(json!({"id": "arni"}))
+            .result::<types::Aria2Status>()
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(JsonRPCError::NotStandardResponse)
+        ));
+    }
+
+    #[test]
+    fn unauthorized_response() {
+        let response = JsonRPCResponse {
+            value: json!({
+                "id": "arni",
+                "jsonrpc": "2.0",
+                "error": {"code": 1, "message": "Unauthorized"}
+            }),
+            method: JsonRPCMethod::GetVersion,
+        };
+        let error = response.result::<types::Aria2Version>().unwrap_err();
+        assert!(matches!(
+            error.downcast::<Error>(),
+            Ok(Error::Aria2Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn multicall_adding_downloads() {
+        let status = JsonRPCBuilder::new("arni")
+            .aria2_tell_status(None, "2089b05ecca3d829")
+            .build()
+            .unwrap();
+        assert!(!status.adds_downloads());
+        let add = JsonRPCBuilder::new("arni")
+            .aria2_add_uri(None, "magnet:?xt=urn:btih:abc", None)
+            .build()
+            .unwrap();
+        assert!(add.adds_downloads());
+        let multicall = JsonRPCBuilder::new("arni")
+            .system_multicall(vec![status, add])
+            .build()
+            .unwrap();
+        assert!(multicall.adds_downloads());
+    }
+}
@@ -0,0 +1,265 @@
+//! Typed results of aria2 rpc methods.
+//!
+//! aria2 sends every number and boolean as a string, they are parsed into proper types here.
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use serde_json::Value;
+
+use super::{rpc_error, JsonRPCError};
+
+/// Result of `aria2.getVersion`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Aria2Version {
+    pub version: String,
+    #[serde(default)]
+    pub enabled_features: Vec<String>,
+}
+
+/// Result of `aria2.tellStatus`, and each element of `aria2.tellActive`, `aria2.tellWaiting`
+/// and `aria2.tellStopped`.
+///
+/// Every field is optional on the wire as the caller may ask for only some keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Aria2Status {
+    pub gid: String,
+    /// One of `active`, `waiting`, `paused`, `error`, `complete` and `removed`
+    pub status: String,
+    #[serde(deserialize_with = "number")]
+    pub total_length: u64,
+    #[serde(deserialize_with = "number")]
+    pub completed_length: u64,
+    #[serde(deserialize_with = "number")]
+    pub upload_length: u64,
+    #[serde(deserialize_with = "number")]
+    pub download_speed: u64,
+    #[serde(deserialize_with = "number")]
+    pub upload_speed: u64,
+    #[serde(deserialize_with = "number")]
+    pub connections: u64,
+    pub info_hash: Option<String>,
+    pub dir: Option<String>,
+    pub files: Vec<Aria2File>,
+    /// Only present for stopped downloads
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+    /// Gids of downloads started from this one, e.g. the torrent download of a metadata download
+    pub followed_by: Vec<String>,
+    pub following: Option<String>,
+    pub belongs_to: Option<String>,
+}
+
+/// Result of `aria2.getFiles`, also part of [`Aria2Status`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Aria2File {
+    #[serde(deserialize_with = "number")]
+    pub index: u64,
+    pub path: String,
+    #[serde(deserialize_with = "number")]
+    pub length: u64,
+    #[serde(deserialize_with = "number")]
+    pub completed_length: u64,
+    #[serde(deserialize_with = "boolean")]
+    pub selected: bool,
+    pub uris: Vec<Aria2Uri>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Aria2Uri {
+    pub uri: String,
+    /// `used` or `waiting`
+    pub status: String,
+}
+
+/// Result of `aria2.getPeers`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Aria2Peer {
+    pub peer_id: String,
+    pub ip: String,
+    #[serde(deserialize_with = "number")]
+    pub port: u16,
+    pub bitfield: String,
+    #[serde(deserialize_with = "boolean")]
+    pub am_choking: bool,
+    #[serde(deserialize_with = "boolean")]
+    pub peer_choking: bool,
+    #[serde(deserialize_with = "number")]
+    pub download_speed: u64,
+    #[serde(deserialize_with = "number")]
+    pub upload_speed: u64,
+    #[serde(deserialize_with = "boolean")]
+    pub seeder: bool,
+}
+
+/// Result of `aria2.getGlobalStat`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Aria2GlobalStat {
+    #[serde(deserialize_with = "number")]
+    pub download_speed: u64,
+    #[serde(deserialize_with = "number")]
+    pub upload_speed: u64,
+    #[serde(deserialize_with = "number")]
+    pub num_active: u64,
+    #[serde(deserialize_with = "number")]
+    pub num_waiting: u64,
+    #[serde(deserialize_with = "number")]
+    pub num_stopped: u64,
+    #[serde(deserialize_with = "number")]
+    pub num_stopped_total: u64,
+}
+
+/// A fault reported by aria2 for one call in `system.multicall`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Aria2Fault {
+    #[serde(alias = "faultCode")]
+    pub code: i64,
+    #[serde(alias = "faultString")]
+    pub message: String,
+}
+
+/// Each element of the result of `system.multicall`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum MulticallResult {
+    /// A one-item array holding the result of the call
+    Ok((Value,)),
+    Fault(Aria2Fault),
+}
+
+impl MulticallResult {
+    /// Same as [`super::JsonRPCResponse::result`] for one call in the batch.
+    pub fn result<T: DeserializeOwned>(self) -> Result<T> {
+        match self {
+            Self::Ok((v,)) => T::deserialize(v).context(JsonRPCError::NotStandardResponse),
+            Self::Fault(fault) => Err(rpc_error(fault.code, &fault.message)),
+        }
+    }
+}
+
+/// Events aria2 pushes to WebSocket clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Aria2Event {
+    #[serde(rename = "aria2.onDownloadStart")]
+    DownloadStart,
+    #[serde(rename = "aria2.onDownloadPause")]
+    DownloadPause,
+    #[serde(rename = "aria2.onDownloadStop")]
+    DownloadStop,
+    #[serde(rename = "aria2.onDownloadComplete")]
+    DownloadComplete,
+    #[serde(rename = "aria2.onDownloadError")]
+    DownloadError,
+    #[serde(rename = "aria2.onBtDownloadComplete")]
+    BtDownloadComplete,
+}
+
+/// A notification from aria2, e.g.
+/// `{"jsonrpc":"2.0","method":"aria2.onDownloadComplete","params":[{"gid":"2089b05ecca3d829"}]}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "RawNotification")]
+pub struct Aria2Notification {
+    pub event: Aria2Event,
+    pub gid: String,
+}
+
+#[derive(Deserialize)]
+struct RawNotification {
+    method: Aria2Event,
+    params: (RawGid,),
+}
+
+#[derive(Deserialize)]
+struct RawGid {
+    gid: String,
+}
+
+impl From<RawNotification> for Aria2Notification {
+    fn from(value: RawNotification) -> Self {
+        Self {
+            event: value.method,
+            gid: value.params.0.gid,
+        }
+    }
+}
+
+fn number<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+}
+
+fn boolean<'de, D>(deserializer: D) -> std::result::Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    number(deserializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_status() {
+        let status: Aria2Status = serde_json::from_value(json!({
+            "gid": "2089b05ecca3d829",
+            "status": "error",
+            "totalLength": "34896138",
+            "completedLength": "0",
+            "errorCode": "3",
+            "errorMessage": "Resource not found",
+            "files": [{
+                "index": "1",
+                "path": "/downloads/file",
+                "length": "34896138",
+                "completedLength": "0",
+                "selected": "true",
+                "uris": [{"status": "used", "uri": "http://example.org/file"}]
+            }]
+        }))
+        .unwrap();
+        assert_eq!(status.total_length, 34896138);
+        assert_eq!(status.error_code.as_deref(), Some("3"));
+        assert!(status.files[0].selected);
+        assert!(status.followed_by.is_empty());
+    }
+
+    #[test]
+    fn parse_multicall() {
+        let results: Vec<MulticallResult> = serde_json::from_value(json!([
+            ["2089b05ecca3d829"],
+            {"code": 1, "message": "Unauthorized"}
+        ]))
+        .unwrap();
+        assert_eq!(
+            results[0],
+            MulticallResult::Ok((json!("2089b05ecca3d829"),))
+        );
+        assert!(matches!(&results[1], MulticallResult::Fault(f) if f.code == 1));
+
+        let mut results = results.into_iter();
+        let gid = results.next().unwrap().result::<String>().unwrap();
+        assert_eq!(gid, "2089b05ecca3d829");
+        let error = results.next().unwrap().result::<String>().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(crate::error::Error::Aria2Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn bad_number() {
+        let stat = serde_json::from_value::<Aria2GlobalStat>(json!({"downloadSpeed": "fast"}));
+        assert!(stat.is_err());
+    }
+}
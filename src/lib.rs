@@ -2,7 +2,10 @@ pub mod app;
 pub mod client;
 pub mod data;
 pub mod error;
+pub mod feed;
 pub mod jsonrpc;
+#[cfg(test)]
+mod testing;
 
 #[cfg(test)]
 mod tests {}
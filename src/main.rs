@@ -1,12 +1,22 @@
-use std::time::Duration;
-
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use arni::{
     app::App,
-    data::{config::Config, history::History},
+    data::{
+        cache::FeedCache,
+        check::{self, Severity},
+        config::Config,
+        file::WorkDirLock,
+        history::History,
+        sqlite::{self, SqliteHistory, SqliteState},
+        state::State,
+        store::{HistoryStore, StateStore, Storage},
+    },
 };
-use clap::Parser;
-use log::{error, info};
+use std::path::Path;
+
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use log::{error, info, warn};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about=None)]
@@ -19,8 +29,38 @@ struct Cli {
     #[arg(short = 'd', long = "working_directory", value_name = "PATH")]
     working_dir: Option<String>,
 
-    #[arg(long)]
+    /// Print what would be done without doing it
+    #[arg(long, global = true)]
     dry_run: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Manage download history
+    History {
+        #[command(subcommand)]
+        command: HistoryCommand,
+    },
+    /// Manage config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum HistoryCommand {
+    /// Remove records expired under the `[retention]` policy in config
+    Prune,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Check config and print problems with their line and column, without running
+    Check,
 }
 
 fn main() -> Result<()> {
@@ -29,46 +69,168 @@ fn main() -> Result<()> {
     info!("Parsing cli args...");
     let cli = Cli::parse();
 
-    info!("Init config...");
     let config = "config.toml";
     let config = if let Some(dir) = &cli.working_dir {
         format!("{dir}/{config}")
     } else {
         config.to_string()
     };
-    let mut config = Config::new(&config)
+
+    // read only, fine while another arni is running
+    if let Some(Command::Config {
+        command: ConfigCommand::Check,
+    }) = cli.command
+    {
+        let text = std::fs::read_to_string(&config)
+            .with_context(|| format!("Fail to read config file {config}."))?;
+        let diagnostics = check::check(&text);
+        for diagnostic in &diagnostics {
+            println!("{config}:{diagnostic}");
+        }
+        let errors = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count();
+        if errors > 0 {
+            bail!("{errors} errors in {config}.");
+        }
+        println!("{config} is valid.");
+        return Ok(());
+    }
+
+    info!("Locking working directory...");
+    let dir = Path::new(cli.working_dir.as_deref().unwrap_or("."));
+    // held until exit, refuses another arni on the same directory
+    let _lock = WorkDirLock::acquire(dir).inspect_err(|e| error!("{e}"))?;
+
+    info!("Init config...");
+    let mut config = Config::new(&config, cli.dry_run)
         .with_context(|| "Init config failed.")
         .map_err(|e| {
             error!("Can't init config: {e}");
             e
         })?;
 
+    let path = |file: &str| {
+        if let Some(dir) = &cli.working_dir {
+            format!("{dir}/{file}")
+        } else {
+            file.to_string()
+        }
+    };
+    let history_path = path("history.toml");
+    let state_path = path("state.toml");
+    let db_path = path("arni.db");
+
+    let mut storage = config.storage();
+    // a dry run doesn't import, the TOML files are what the database would start with
+    if cli.dry_run && storage == Storage::Sqlite && sqlite::is_empty(&db_path)? {
+        info!("{db_path} has nothing yet, reading TOML files instead.");
+        storage = Storage::Toml;
+    }
+
     info!("Init history...");
-    let history = "history.toml";
-    let history = if let Some(dir) = &cli.working_dir {
-        format!("{dir}/{history}")
+    let mut toml_history;
+    let mut sqlite_history;
+    let history: &mut dyn HistoryStore = match storage {
+        Storage::Toml => {
+            toml_history = History::new(&history_path, cli.dry_run)
+                .with_context(|| "Init history failed.")
+                .inspect_err(|e| error!("Can't init history: {e}"))?;
+            &mut toml_history
+        }
+        Storage::Sqlite => {
+            if !cli.dry_run {
+                sqlite::import_toml(&db_path, &history_path, &state_path)
+                    .inspect_err(|e| error!("Can't import TOML files: {e}"))?;
+            }
+            sqlite_history = SqliteHistory::new(&db_path, cli.dry_run)
+                .with_context(|| "Init history failed.")
+                .inspect_err(|e| error!("Can't init history: {e}"))?;
+            &mut sqlite_history
+        }
+    };
+
+    if let Some(Command::History {
+        command: HistoryCommand::Prune,
+    }) = cli.command
+    {
+        history.set_retention(config.retention());
+        let pruned = history.prune(Utc::now(), cli.dry_run)?;
+        for record in &pruned {
+            let name = record.title.as_deref().unwrap_or(&record.guid);
+            println!("{name}");
+        }
+        if cli.dry_run {
+            println!("{} records would be pruned.", pruned.len());
+        } else {
+            history.sync()?;
+            println!("{} records pruned.", pruned.len());
+        }
+        return Ok(());
+    }
+
+    info!("Init state...");
+    let mut toml_state;
+    let mut sqlite_state;
+    let state: &mut dyn StateStore = match storage {
+        Storage::Toml => {
+            toml_state = State::new(&state_path, cli.dry_run)
+                .with_context(|| "Init state failed.")
+                .inspect_err(|e| error!("Can't init state: {e}"))?;
+            &mut toml_state
+        }
+        Storage::Sqlite => {
+            sqlite_state = SqliteState::new(&db_path, cli.dry_run)
+                .with_context(|| "Init state failed.")
+                .inspect_err(|e| error!("Can't init state: {e}"))?;
+            &mut sqlite_state
+        }
+    };
+
+    info!("Init cache...");
+    let cache = "cache.toml";
+    let cache = if let Some(dir) = &cli.working_dir {
+        format!("{dir}/{cache}")
     } else {
-        history.to_string()
+        cache.to_string()
     };
-    let mut history = History::new(&history)
-        .with_context(|| "Init history failed.")
+    let mut cache = FeedCache::new(&cache, cli.dry_run)
+        .with_context(|| "Init cache failed.")
         .map_err(|e| {
-            error!("Can't init history: {e}");
+            error!("Can't init cache: {e}");
             e
         })?;
 
     info!("Starting app...");
-    let mut app = App::new(&mut config, &mut history)?;
+    let mut app = App::new(&mut config, history, state, &mut cache)?;
 
     if cli.watch {
         info!("Entering watch mode.");
+        if let Err(e) = app.watch_config() {
+            warn!("Can't watch config, edits are picked up on the next run: {e}");
+        }
         loop {
-            let _ = app.run(cli.dry_run);
-            std::thread::sleep(Duration::from_secs(3600));
+            let timeout = match app.run(cli.dry_run) {
+                Ok(report) => {
+                    println!("{report}");
+                    app.until_next_run()
+                }
+                // feeds that were not fetched are still due, don't retry right away
+                Err(e) => {
+                    error!("{e:#}");
+                    app.config.interval()
+                }
+            };
+            // download status changes are followed through notifications meanwhile
+            if let Err(e) = app.wait(timeout, cli.dry_run) {
+                error!("Fail to follow download status: {e}");
+            }
         }
     } else {
         info!("Entering one-shot mode.");
-        app.run(cli.dry_run)?;
+        let report = app.run(cli.dry_run)?;
+        println!("{report}");
     }
     info!("Shutting down...");
     Ok(())